@@ -0,0 +1,282 @@
+use crate::dashboard::{DashboardField, GameDashboard};
+use crate::field_map::Scope;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Condition {
+    Below,
+    Above,
+}
+
+/// A threshold on a dashboard field, e.g. `fuel_stockpile` of Earth below 1,000,000.
+///
+/// Population rules check the population fields and the deposits, e.g.
+/// `gallicite_accessibility`, game rules check the game fields, the custom metrics and the empire
+/// totals.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AlertRule {
+    pub field: String,
+    #[serde(default)]
+    pub scope: Scope,
+    /// population name, every population when absent
+    #[serde(default)]
+    pub population: Option<String>,
+    #[serde(default)]
+    pub below: Option<f64>,
+    #[serde(default)]
+    pub above: Option<f64>,
+}
+
+/// A field that crossed the threshold of a rule
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Alert {
+    pub game_id: u32,
+    pub game_time: f64,
+    /// game calendar date in `YYYY-MM-DDTHH:MM:SS` format
+    pub date: String,
+    pub population_id: Option<u32>,
+    pub population_name: Option<String>,
+    pub field: String,
+    pub value: f64,
+    pub condition: Condition,
+    pub threshold: f64,
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Condition::Below => write!(f, "below"),
+            Condition::Above => write!(f, "above"),
+        }
+    }
+}
+
+impl fmt::Display for Alert {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ", self.date)?;
+        if let Some(name) = &self.population_name {
+            write!(f, "{} ", name)?;
+        }
+        write!(
+            f,
+            "{} {} {} {}",
+            self.field, self.value, self.condition, self.threshold
+        )
+    }
+}
+
+impl AlertRule {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.below.is_none() && self.above.is_none() {
+            return Err(format!("alert on {} requires below or above", self.field));
+        }
+        for threshold in self.below.iter().chain(self.above.iter()) {
+            if !threshold.is_finite() {
+                return Err(format!(
+                    "alert on {} has a threshold that isn't a finite number",
+                    self.field
+                ));
+            }
+        }
+        // values under `below` or over `above` are raised, so `below >= above` raises every value
+        if let (Some(below), Some(above)) = (self.below, self.above) {
+            if below >= above {
+                return Err(format!(
+                    "alert on {} is always raised, below must be lower than above",
+                    self.field
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether `names`, the fields of the rule scope, have the field of the rule. Deposit fields
+    /// depend on the minerals of each body and are always known.
+    pub fn is_known(&self, names: &[String]) -> bool {
+        let deposit = self.scope == Scope::Population
+            && (self.field.ends_with("_amount") || self.field.ends_with("_accessibility"));
+        deposit || names.contains(&self.field)
+    }
+
+    fn triggered(&self, value: f64) -> Option<(Condition, f64)> {
+        match (self.below, self.above) {
+            (Some(below), _) if value < below => Some((Condition::Below, below)),
+            (_, Some(above)) if value > above => Some((Condition::Above, above)),
+            _ => None,
+        }
+    }
+
+    /// Alerts of `game`, raised when a value crosses the threshold. A value staying past the
+    /// threshold isn't raised again until it gets back within it.
+    pub fn check(&self, game: &GameDashboard) -> Vec<Alert> {
+        let mut alerts = vec![];
+
+        let mut check_field = |fields: &[&[DashboardField]], population: Option<(u32, &str)>| {
+            let field = fields
+                .iter()
+                .flat_map(|fields| fields.iter())
+                .find(|f| f.name == self.field);
+            let field = match field {
+                Some(field) => field,
+                None => return,
+            };
+
+            let mut previous = None;
+            for value in &field.historical {
+                let triggered = self.triggered(value.y);
+                if let Some((condition, threshold)) = triggered {
+                    if previous != Some(condition) {
+                        alerts.push(Alert {
+                            game_id: game.game_id,
+                            game_time: value.x,
+                            date: game.date(value.x).to_iso_datetime(),
+                            population_id: population.map(|(id, _)| id),
+                            population_name: population.map(|(_, name)| name.to_string()),
+                            field: self.field.clone(),
+                            value: value.y,
+                            condition,
+                            threshold,
+                        });
+                    }
+                }
+                previous = triggered.map(|(condition, _)| condition);
+            }
+        };
+
+        match self.scope {
+            Scope::Game => check_field(&[&game.fields, &game.custom, &game.totals()], None),
+            Scope::Population => {
+                let populations = game.populations.iter().filter(|pop| {
+                    self.population
+                        .as_ref()
+                        .is_none_or(|name| name.eq_ignore_ascii_case(&pop.population_name))
+                });
+                for pop in populations {
+                    check_field(
+                        &[&pop.fields, &pop.deposits],
+                        Some((pop.population_id, &pop.population_name)),
+                    );
+                }
+            }
+        }
+
+        alerts
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::aurora_db::AuroraData;
+    use crate::dashboard::test::game_data;
+    use crate::dashboard::Dashboard;
+
+    #[test]
+    fn alerts_should_be_raised_when_crossing_thresholds() {
+        let mut dashboard = Dashboard::new();
+        let fuel = [5.0, 0.5, 0.2, 3.0, 0.1];
+        dashboard.append(&AuroraData {
+            games: fuel
+                .iter()
+                .enumerate()
+                .map(|(i, fuel)| {
+                    let mut data = game_data(i as f64);
+                    data.populations[0]
+                        .values
+                        .insert("fuel_stockpile".to_string(), *fuel);
+                    data
+                })
+                .collect(),
+        });
+
+        let rules: Vec<AlertRule> = serde_json::from_str(
+            r#"[
+                {"field": "fuel_stockpile", "population": "pop 1", "below": 1},
+                {"field": "neutronium_accessibility", "below": 0.9},
+                {"field": "wealth", "scope": "game", "above": 100},
+                {"field": "population", "population": "Mars", "below": 100}
+            ]"#,
+        )
+        .unwrap();
+        dashboard.evaluate_alerts(&rules);
+
+        let alerts = &dashboard.games[0].alerts;
+        let summary: Vec<(f64, &str, f64)> = alerts
+            .iter()
+            .map(|a| (a.game_time, a.field.as_str(), a.value))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (0.0, "neutronium_accessibility", 0.8),
+                (1.0, "fuel_stockpile", 0.5),
+                (4.0, "fuel_stockpile", 0.1)
+            ]
+        );
+        assert_eq!(alerts[1].population_name, Some("Pop 1".to_string()));
+        assert_eq!(alerts[1].condition, Condition::Below);
+        assert_eq!(
+            alerts[1].to_string(),
+            "0002-01-01T00:00:01 Pop 1 fuel_stockpile 0.5 below 1"
+        );
+    }
+
+    #[test]
+    fn game_alerts_should_check_empire_totals() {
+        let mut second_pop = game_data(1.0);
+        second_pop.populations[0].population_id = 2;
+
+        let mut dashboard = Dashboard::new();
+        dashboard.append(&AuroraData {
+            games: vec![game_data(0.0), game_data(1.0), second_pop],
+        });
+
+        let rule: AlertRule =
+            serde_json::from_str(r#"{"field": "population", "scope": "game", "above": 4}"#)
+                .unwrap();
+        dashboard.evaluate_alerts(&[rule]);
+
+        let alerts = &dashboard.games[0].alerts;
+        assert_eq!(alerts.len(), 1);
+        assert_eq!((alerts[0].game_time, alerts[0].value), (1.0, 6.0));
+        assert_eq!(alerts[0].population_id, None);
+    }
+
+    #[test]
+    fn alert_rules_should_reject_invalid_thresholds() {
+        let rule = |below: Option<f64>, above: Option<f64>| AlertRule {
+            field: "fuel_stockpile".to_string(),
+            scope: Scope::Population,
+            population: None,
+            below,
+            above,
+        };
+
+        assert!(rule(Some(1.0), None).validate().is_ok());
+        assert!(rule(Some(1.0), Some(5.0)).validate().is_ok());
+        assert!(rule(None, None).validate().is_err());
+        assert!(rule(Some(f64::NAN), None).validate().is_err());
+        assert!(rule(None, Some(f64::INFINITY)).validate().is_err());
+        assert!(rule(Some(5.0), Some(1.0)).validate().is_err());
+        assert!(rule(Some(2.0), Some(2.0)).validate().is_err());
+    }
+
+    #[test]
+    fn alert_rules_should_know_field_names() {
+        let rules: Vec<AlertRule> = serde_json::from_str(
+            r#"[
+                {"field": "fuel_stockpile", "below": 1},
+                {"field": "gallicite_accessibility", "below": 0.2},
+                {"field": "fuel_stockpil", "below": 1},
+                {"field": "wealth_amount", "scope": "game", "below": 1}
+            ]"#,
+        )
+        .unwrap();
+        let names = vec!["fuel_stockpile".to_string(), "wealth".to_string()];
+
+        let known: Vec<bool> = rules.iter().map(|rule| rule.is_known(&names)).collect();
+        assert_eq!(known, vec![true, true, false, false]);
+    }
+}
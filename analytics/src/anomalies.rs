@@ -0,0 +1,181 @@
+use crate::dashboard::{DashboardField, GameDashboard, HistoricalValue};
+use serde::{Deserialize, Serialize};
+
+/// Settings of the anomaly detection
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct AnomalyCfg {
+    /// previous changes used as the recent trend
+    pub window: usize,
+    /// deviations from the trend, in standard deviations, flagged as anomalies
+    pub threshold: f64,
+}
+
+impl Default for AnomalyCfg {
+    fn default() -> Self {
+        AnomalyCfg {
+            window: 10,
+            threshold: 3.0,
+        }
+    }
+}
+
+/// changes needed before a value can be flagged
+const MIN_HISTORY: usize = 3;
+/// smallest deviation, relative to the previous value, so rounding in flat series isn't flagged
+const MIN_DEVIATION: f64 = 0.01;
+
+impl AnomalyCfg {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.window < MIN_HISTORY {
+            return Err(format!(
+                "anomaly_window must be at least {}, got {}",
+                MIN_HISTORY, self.window
+            ));
+        }
+        if !self.threshold.is_finite() || self.threshold <= 0.0 {
+            return Err(format!(
+                "anomaly_threshold must be a positive number, got {}",
+                self.threshold
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// An unusual jump of a field compared with its recent trend
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Anomaly {
+    pub game_id: u32,
+    /// `None` for game fields, e.g. wealth
+    pub population_id: Option<u32>,
+    pub population_name: Option<String>,
+    pub field: String,
+    pub game_time: f64,
+    /// game calendar date in `YYYY-MM-DDTHH:MM:SS` format
+    pub date: String,
+    pub previous: f64,
+    pub value: f64,
+    /// change expected from the recent trend
+    pub expected: f64,
+    /// deviation from the expected change in standard deviations, negative for drops
+    pub score: f64,
+}
+
+/// `(index, expected change, score)` of the values deviating from the trend of the `window`
+/// previous changes.
+///
+/// Changes are compared as rates over game time so irregular snapshots don't look like jumps.
+fn detect_values(values: &[HistoricalValue], cfg: &AnomalyCfg) -> Vec<(usize, f64, f64)> {
+    let mut anomalies = vec![];
+    let mut rates: Vec<f64> = vec![];
+
+    for i in 1..values.len() {
+        let elapsed = values[i].x - values[i - 1].x;
+        let change = values[i].y - values[i - 1].y;
+        if elapsed <= 0.0 {
+            continue;
+        }
+
+        let recent = &rates[rates.len().saturating_sub(cfg.window)..];
+        if recent.len() >= MIN_HISTORY {
+            let mean = recent.iter().sum::<f64>() / recent.len() as f64;
+            let variance =
+                recent.iter().map(|r| (r - mean).powi(2)).sum::<f64>() / recent.len() as f64;
+
+            let expected = mean * elapsed;
+            let deviation = (variance.sqrt() * elapsed)
+                .max(values[i - 1].y.abs() * MIN_DEVIATION)
+                .max(f64::EPSILON);
+            let score = (change - expected) / deviation;
+
+            if score.abs() >= cfg.threshold {
+                anomalies.push((i, expected, score));
+            }
+        }
+
+        rates.push(change / elapsed);
+    }
+
+    anomalies
+}
+
+impl AnomalyCfg {
+    /// anomalies of the game and population fields of `game`, ordered by game time
+    pub fn detect(&self, game: &GameDashboard) -> Vec<Anomaly> {
+        let mut anomalies = vec![];
+
+        let mut detect_fields = |fields: &[DashboardField], population: Option<(u32, &str)>| {
+            for field in fields {
+                for (i, expected, score) in detect_values(&field.historical, self) {
+                    let value = &field.historical[i];
+                    anomalies.push(Anomaly {
+                        game_id: game.game_id,
+                        population_id: population.map(|(id, _)| id),
+                        population_name: population.map(|(_, name)| name.to_string()),
+                        field: field.name.clone(),
+                        game_time: value.x,
+                        date: game.date(value.x).to_iso_datetime(),
+                        previous: field.historical[i - 1].y,
+                        value: value.y,
+                        expected,
+                        score,
+                    });
+                }
+            }
+        };
+
+        detect_fields(&game.fields, None);
+        for pop in &game.populations {
+            detect_fields(&pop.fields, Some((pop.population_id, &pop.population_name)));
+        }
+
+        anomalies.sort_by(|a, b| a.game_time.total_cmp(&b.game_time));
+        anomalies
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn values(ys: &[f64]) -> Vec<HistoricalValue> {
+        ys.iter()
+            .enumerate()
+            .map(|(i, y)| HistoricalValue {
+                x: i as f64 * 10.0,
+                y: *y,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn anomalies_should_flag_jumps_from_trend() {
+        let cfg = AnomalyCfg::default();
+
+        // steady growth then a bombardment
+        let growth = values(&[100.0, 102.0, 104.0, 106.1, 108.0, 110.0, 60.0, 62.0]);
+        let anomalies = detect_values(&growth, &cfg);
+        assert_eq!(anomalies.len(), 1);
+        assert_eq!(anomalies[0].0, 6);
+        assert!((anomalies[0].1 - 2.0).abs() < 0.1);
+        assert!(anomalies[0].2 < -3.0);
+
+        // a flat stockpile with rounding noise and a theft
+        let flat = values(&[500.0, 500.0, 500.1, 500.0, 499.9, 500.0, 120.0]);
+        let anomalies = detect_values(&flat, &cfg);
+        assert_eq!(anomalies.len(), 1);
+        assert_eq!(anomalies[0].0, 6);
+
+        // noisy series without jumps
+        let noisy = values(&[100.0, 110.0, 95.0, 105.0, 100.0, 112.0, 94.0, 103.0]);
+        assert!(detect_values(&noisy, &cfg).is_empty());
+
+        // irregular snapshots keep the same rate
+        let mut irregular = values(&[0.0, 10.0, 20.0, 30.0, 40.0]);
+        irregular.push(HistoricalValue { x: 100.0, y: 100.0 });
+        assert!(detect_values(&irregular, &cfg).is_empty());
+
+        // not enough history
+        assert!(detect_values(&values(&[10.0, 10.0, 500.0]), &cfg).is_empty());
+    }
+}
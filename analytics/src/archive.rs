@@ -0,0 +1,332 @@
+use crate::aurora_db::{AuroraData, FCTGame};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+const ARCHIVE_EXTENSION: &str = "db";
+/// time of the copy at the end of the archive names
+const TIMESTAMP_FORMAT: &str = "%Y%m%d-%H%M%S";
+
+#[derive(Debug)]
+pub enum ArchiveError {
+    Generic(String),
+}
+
+/// Where archives of the Aurora database are kept and how many of them
+#[derive(Debug, Clone)]
+pub struct ArchiveCfg {
+    pub dir: String,
+    /// oldest archives are removed when there are more than this
+    pub max_count: Option<usize>,
+    /// oldest archives are removed while all archives together are bigger than this, in bytes
+    pub max_size: Option<u64>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ArchiveEntry {
+    pub name: String,
+    pub path: PathBuf,
+    pub size: u64,
+    pub modified: SystemTime,
+}
+
+/// Copy the Aurora database into the archive folder, named by the game name, the game date and
+/// the current time, e.g. `Sol_Campaign_2039-06-01T12-03-50_20240101-183000.db`. Old archives are
+/// removed afterwards according to the limits. Returns the new archive.
+pub fn archive_db(
+    cfg: &ArchiveCfg,
+    db_path: &str,
+    data: &AuroraData,
+) -> Result<PathBuf, ArchiveError> {
+    std::fs::create_dir_all(&cfg.dir)
+        .map_err(|e| ArchiveError::Generic(format!("fail to create {}: {}", cfg.dir, e)))?;
+
+    let game = data
+        .games
+        .iter()
+        .find(|agame| agame.game.is_last())
+        .or_else(|| data.games.first())
+        .map(|agame| &agame.game);
+
+    let base_name = archive_name(game, chrono::Local::now().naive_local());
+    let mut path = Path::new(&cfg.dir).join(format!("{}.{}", base_name, ARCHIVE_EXTENSION));
+    let mut suffix = 1;
+    while path.exists() {
+        suffix += 1;
+        path = Path::new(&cfg.dir).join(format!("{}_{}.{}", base_name, suffix, ARCHIVE_EXTENSION));
+    }
+
+    std::fs::copy(db_path, &path).map_err(|e| {
+        ArchiveError::Generic(format!(
+            "fail to copy {} into {}: {}",
+            db_path,
+            path.display(),
+            e
+        ))
+    })?;
+
+    rotate(cfg)?;
+    Ok(path)
+}
+
+fn archive_name(game: Option<&FCTGame>, now: chrono::NaiveDateTime) -> String {
+    let game_part = match game {
+        Some(game) => {
+            let name: String = game
+                .game_name
+                .chars()
+                .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+                .collect();
+            format!(
+                "{}_{}",
+                name,
+                game.date().to_iso_datetime().replace(':', "-")
+            )
+        }
+        None => "AuroraDB".to_string(),
+    };
+
+    format!("{}_{}", game_part, now.format(TIMESTAMP_FORMAT))
+}
+
+/// Archives in the folder, oldest first by the time in their names since a copy keeps the
+/// modification time of the database on some systems. Other files, e.g. backups copied by hand,
+/// are ignored.
+pub fn list(dir: &str) -> Result<Vec<ArchiveEntry>, ArchiveError> {
+    let read_dir = match std::fs::read_dir(dir) {
+        Ok(read_dir) => read_dir,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => {
+            return Err(ArchiveError::Generic(format!(
+                "fail to read {}: {}",
+                dir, e
+            )))
+        }
+    };
+
+    let mut entries = vec![];
+    for entry in read_dir {
+        let entry =
+            entry.map_err(|e| ArchiveError::Generic(format!("fail to read {}: {}", dir, e)))?;
+        let path = entry.path();
+        let name = entry.file_name().to_string_lossy().to_string();
+        let key = match parse_name(&name) {
+            Some(key) => key,
+            None => continue,
+        };
+
+        let metadata = entry.metadata().map_err(|e| {
+            ArchiveError::Generic(format!("fail to read {}: {}", path.display(), e))
+        })?;
+        if !metadata.is_file() {
+            continue;
+        }
+
+        entries.push((
+            key,
+            ArchiveEntry {
+                name,
+                path,
+                size: metadata.len(),
+                modified: metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH),
+            },
+        ));
+    }
+
+    entries.sort_by_key(|(key, _)| *key);
+    Ok(entries.into_iter().map(|(_, entry)| entry).collect())
+}
+
+/// Time of the copy and the `_<n>` suffix added to archives taken in the same second, so `_10`
+/// sorts after `_2`. `None` when the name isn't one given by `archive_db`.
+fn parse_name(name: &str) -> Option<(chrono::NaiveDateTime, u32)> {
+    let stem = name
+        .strip_suffix(ARCHIVE_EXTENSION)
+        .and_then(|stem| stem.strip_suffix('.'))?;
+
+    let (base, sequence) = match stem.rsplit_once('_') {
+        Some((base, suffix))
+            if !suffix.is_empty() && suffix.chars().all(|c| c.is_ascii_digit()) =>
+        {
+            (base, suffix.parse().ok()?)
+        }
+        _ => (stem, 1),
+    };
+
+    // `<game part>_<time of the copy>`, the time has a fixed length
+    let split = base.len().checked_sub("20240101-183000".len())?;
+    let (game_part, time) = (base.get(..split)?, base.get(split..)?);
+    if game_part.len() < 2 || !game_part.ends_with('_') {
+        return None;
+    }
+
+    let time = chrono::NaiveDateTime::parse_from_str(time, TIMESTAMP_FORMAT).ok()?;
+    Some((time, sequence))
+}
+
+/// remove the oldest archives until the limits are respected, the newest is always kept
+fn rotate(cfg: &ArchiveCfg) -> Result<(), ArchiveError> {
+    let mut entries = list(&cfg.dir)?;
+    let mut total_size: u64 = entries.iter().map(|e| e.size).sum();
+
+    while entries.len() > 1 {
+        let too_many = cfg
+            .max_count
+            .map(|max| entries.len() > max)
+            .unwrap_or(false);
+        let too_big = cfg.max_size.map(|max| total_size > max).unwrap_or(false);
+        if !too_many && !too_big {
+            break;
+        }
+
+        let oldest = entries.remove(0);
+        std::fs::remove_file(&oldest.path).map_err(|e| {
+            ArchiveError::Generic(format!("fail to remove {}: {}", oldest.path.display(), e))
+        })?;
+        total_size -= oldest.size;
+    }
+
+    Ok(())
+}
+
+/// Replace the Aurora database by the archive `name`. The current database is kept as
+/// `AuroraDB.db.bak` in case the wrong archive was restored.
+pub fn restore(dir: &str, name: &str, db_path: &str) -> Result<PathBuf, ArchiveError> {
+    let entry = list(dir)?
+        .into_iter()
+        .find(|entry| entry.name == name)
+        .ok_or_else(|| ArchiveError::Generic(format!("archive {} not found in {}", name, dir)))?;
+
+    if Path::new(db_path).exists() {
+        let backup = format!("{}.bak", db_path);
+        std::fs::copy(db_path, &backup)
+            .map_err(|e| ArchiveError::Generic(format!("fail to copy {}: {}", db_path, e)))?;
+    }
+
+    // copy next to the database and rename so Aurora never sees a partial file
+    let tmp_path = format!("{}.tmp", db_path);
+    std::fs::copy(&entry.path, &tmp_path).map_err(|e| {
+        ArchiveError::Generic(format!("fail to copy {}: {}", entry.path.display(), e))
+    })?;
+    std::fs::rename(&tmp_path, db_path)
+        .map_err(|e| ArchiveError::Generic(format!("fail to write {}: {}", db_path, e)))?;
+
+    Ok(entry.path)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::dashboard::test::game_data;
+
+    fn temp_dir(name: &str) -> String {
+        let path =
+            std::env::temp_dir().join(format!("aurora_analytics_{}_{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        path.to_string_lossy().to_string()
+    }
+
+    #[test]
+    fn archive_name_should_have_game_and_dates() {
+        let mut game = game_data(86400.0).game;
+        game.game_name = "Sol: Campaign".to_string();
+        let now = chrono::NaiveDate::from_ymd_opt(2024, 1, 1)
+            .and_then(|d| d.and_hms_opt(18, 30, 0))
+            .unwrap();
+
+        assert_eq!(
+            archive_name(Some(&game), now),
+            "Sol__Campaign_0002-01-02T00-00-00_20240101-183000"
+        );
+        assert_eq!(archive_name(None, now), "AuroraDB_20240101-183000");
+    }
+
+    #[test]
+    fn archive_should_rotate_and_restore() {
+        let dir = temp_dir("archive");
+        let db_path = format!("{}/AuroraDB.db", dir);
+        let archive_dir = format!("{}/archive", dir);
+        let cfg = ArchiveCfg {
+            dir: archive_dir.clone(),
+            max_count: Some(2),
+            max_size: None,
+        };
+        let data = AuroraData {
+            games: vec![game_data(1.0)],
+        };
+
+        // copied by hand, never listed nor removed
+        std::fs::create_dir_all(&archive_dir).unwrap();
+        let backup = format!("{}/AuroraDB_before_invasion.db", archive_dir);
+        std::fs::write(&backup, "backup").unwrap();
+
+        for content in &["save 1", "save 2", "save 3"] {
+            std::fs::write(&db_path, content).unwrap();
+            archive_db(&cfg, &db_path, &data).unwrap();
+        }
+
+        let entries = list(&archive_dir).unwrap();
+        assert_eq!(entries.len(), 2);
+        let contents: Vec<String> = entries
+            .iter()
+            .map(|e| std::fs::read_to_string(&e.path).unwrap())
+            .collect();
+        assert_eq!(contents, vec!["save 2", "save 3"]);
+        assert!(Path::new(&backup).exists());
+
+        restore(&archive_dir, &entries[0].name, &db_path).unwrap();
+        assert_eq!(std::fs::read_to_string(&db_path).unwrap(), "save 2");
+        assert_eq!(
+            std::fs::read_to_string(format!("{}.bak", db_path)).unwrap(),
+            "save 3"
+        );
+        assert!(restore(&archive_dir, "missing.db", &db_path).is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn archive_should_list_by_time_in_name_and_suffix() {
+        let dir = temp_dir("archive_suffix");
+        let base = "Sol_2039-06-01T12-03-50_20240101-183000";
+        let names = [
+            format!("{}_10.db", base),
+            format!("{}_2.db", base),
+            "Sol_2039-06-01T12-03-50_20231231-235959.db".to_string(),
+            format!("{}.db", base),
+            format!("{}_9.db", base),
+            "AuroraDB_20240102-080000.db".to_string(),
+            "Sol_backup.db".to_string(),
+            "20240101-183000.db".to_string(),
+            format!("{}.db.tmp", base),
+        ];
+
+        // copies can keep the modification time of the database, it must not matter
+        for (i, name) in names.iter().enumerate() {
+            let path = format!("{}/{}", dir, name);
+            std::fs::write(&path, name).unwrap();
+            let modified =
+                SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_700_000_000 - i as u64);
+            std::fs::File::options()
+                .write(true)
+                .open(&path)
+                .and_then(|file| file.set_modified(modified))
+                .unwrap();
+        }
+
+        let names: Vec<String> = list(&dir).unwrap().into_iter().map(|e| e.name).collect();
+        assert_eq!(
+            names,
+            vec![
+                "Sol_2039-06-01T12-03-50_20231231-235959.db".to_string(),
+                format!("{}.db", base),
+                format!("{}_2.db", base),
+                format!("{}_9.db", base),
+                format!("{}_10.db", base),
+                "AuroraDB_20240102-080000.db".to_string(),
+            ]
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,5 +1,11 @@
+use crate::aurora_schema::{AuroraSchema, QuerySet};
+use crate::calendar::GameDate;
+use crate::field_map::{FieldMap, Scope};
+use crate::metrics::MetricSet;
+use indexmap::IndexMap;
 use rusqlite::{Connection, OpenFlags};
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use std::path::{Path, PathBuf};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -20,49 +26,61 @@ impl FCTGame {
         self.game_time as u32 / (60 * 60 * 24 * 365) + self.start_year
     }
 
-    // pub fn month(&self) -> u32 {
-    //     self.game_time as u32 / (60 * 60 * 24 * 365) + self.start_year
-    // }
+    pub fn date(&self) -> GameDate {
+        GameDate::from_game_time(self.start_year, self.game_time)
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(from = "StoredPopulation")]
 pub struct FCTPopulation {
     pub population_id: u32,
     pub system_id: Option<u32>,
     pub system_body_id: Option<u32>,
     pub pop_name: String,
-    pub fuel_stockpile: f64,
-    pub maintenance_stockpile: f64,
-    pub population: f64,
-    pub duranium: f64,
-    pub neutronium: f64,
-    pub corbomite: f64,
-    pub tritanium: f64,
-    pub boronide: f64,
-    pub mercassium: f64,
-    pub vendarite: f64,
-    pub sorium: f64,
-    pub corundium: f64,
-    /// optional as old don't have this field
-    pub uridium: Option<f64>,
-    pub gallicite: f64,
     /// optional as old don't have this field
     pub minerals: Option<Vec<FCTMineralDeposit>>,
+    /// values read through the field maps, by dashboard field name
+    pub values: IndexMap<String, f64>,
 }
 
-// #[test]
-// fn test_fctgame_month() {
-//     let seconds = 454_853_030;
-//     let date = NaiveDateTime::from_timestamp(seconds, 0);
-//     println!("{:?}", date);
-//     panic!("{:?}", date);
-// }
+/// A population as found in the dump, older versions stored the built-in fields as members
+#[derive(Deserialize)]
+struct StoredPopulation {
+    population_id: u32,
+    system_id: Option<u32>,
+    system_body_id: Option<u32>,
+    pop_name: String,
+    minerals: Option<Vec<FCTMineralDeposit>>,
+    #[serde(default)]
+    values: IndexMap<String, f64>,
+    #[serde(flatten)]
+    legacy: IndexMap<String, Value>,
+}
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct FCTRace {
-    pub race_id: u32,
-    pub wealth: f64,
-    pub annual_wealth: f64,
+impl From<StoredPopulation> for FCTPopulation {
+    fn from(stored: StoredPopulation) -> Self {
+        let mut values = stored.values;
+        merge_legacy_values(&mut values, stored.legacy);
+
+        FCTPopulation {
+            population_id: stored.population_id,
+            system_id: stored.system_id,
+            system_body_id: stored.system_body_id,
+            pop_name: stored.pop_name,
+            minerals: stored.minerals,
+            values,
+        }
+    }
+}
+
+/// numbers of an old snapshot stored by field name, missing values were stored as `null`
+fn merge_legacy_values(values: &mut IndexMap<String, f64>, legacy: IndexMap<String, Value>) {
+    for (name, value) in legacy {
+        if let Some(value) = value.as_f64() {
+            values.entry(name).or_insert(value);
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -72,12 +90,72 @@ pub struct FCTMineralDeposit {
     pub acc: f64,
 }
 
+impl FCTMineralDeposit {
+    /// mineral name of the deposit, e.g. `gallicite`, or `material_<id>` for unknown ids
+    pub fn mineral(&self) -> String {
+        const MINERALS: [&str; 11] = [
+            "duranium",
+            "neutronium",
+            "corbomite",
+            "tritanium",
+            "boronide",
+            "mercassium",
+            "vendarite",
+            "sorium",
+            "uridium",
+            "corundium",
+            "gallicite",
+        ];
+
+        match self.material_id {
+            id @ 1..=11 => MINERALS[id as usize - 1].to_string(),
+            id => format!("material_{}", id),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(from = "StoredGameData")]
 pub struct AuroraGameData {
     pub game: FCTGame,
     pub race_id: u32,
-    pub race: Option<FCTRace>,
     pub populations: Vec<FCTPopulation>,
+    /// values read through the field maps, by dashboard field name
+    pub values: IndexMap<String, f64>,
+    /// results of the user defined metrics, by metric name
+    pub custom: IndexMap<String, f64>,
+}
+
+/// A game as found in the dump, older versions stored the race wealth in a `race` member
+#[derive(Deserialize)]
+struct StoredGameData {
+    game: FCTGame,
+    race_id: u32,
+    #[serde(default)]
+    race: Option<IndexMap<String, Value>>,
+    populations: Vec<FCTPopulation>,
+    #[serde(default)]
+    values: IndexMap<String, f64>,
+    #[serde(default)]
+    custom: IndexMap<String, f64>,
+}
+
+impl From<StoredGameData> for AuroraGameData {
+    fn from(stored: StoredGameData) -> Self {
+        let mut values = stored.values;
+        if let Some(mut race) = stored.race {
+            race.shift_remove("race_id");
+            merge_legacy_values(&mut values, race);
+        }
+
+        AuroraGameData {
+            game: stored.game,
+            race_id: stored.race_id,
+            populations: stored.populations,
+            values,
+            custom: stored.custom,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -90,71 +168,158 @@ pub enum DbError {
     Generic(String),
 }
 
-#[derive(Debug)]
+fn sql_error(e: rusqlite::Error) -> DbError {
+    DbError::Generic(format!("query failed: {}", e))
+}
+
+/// An Aurora database, the default one has no path and is a template for `at`
+#[derive(Debug, Clone, Default)]
 pub struct AuroraDb {
     path: PathBuf,
+    field_map: FieldMap,
+    metrics: MetricSet,
 }
 
 impl AuroraDb {
     pub fn new(path: &Path) -> Self {
-        AuroraDb { path: path.into() }
+        AuroraDb {
+            path: path.into(),
+            field_map: FieldMap::default(),
+            metrics: MetricSet::default(),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
     }
 
-    pub fn fetch(&self) -> Result<AuroraData, DbError> {
+    /// another database read with the same field map and metrics
+    pub fn at(&self, path: &Path) -> Self {
+        AuroraDb {
+            path: path.into(),
+            ..self.clone()
+        }
+    }
+
+    /// also read the columns of `field_map` on every fetch, after the built-in ones
+    pub fn with_field_map(mut self, field_map: FieldMap) -> Self {
+        self.field_map = field_map;
+        self
+    }
+
+    fn open(&self) -> Result<Connection, DbError> {
         let flags = OpenFlags::SQLITE_OPEN_READ_ONLY;
-        let connection =
-            Connection::open_with_flags(self.path.as_path(), flags).expect("fail to open database");
+        Connection::open_with_flags(self.path.as_path(), flags)
+            .map_err(|e| DbError::Generic(format!("fail to open database {:?}: {}", self.path, e)))
+    }
 
-        let games = self.fetch_games(&connection);
+    /// also evaluate the user defined `metrics` on every fetch
+    pub fn with_metrics(mut self, metrics: MetricSet) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Dashboard fields read into the games or the populations, built-in and from the field map.
+    /// Games also have the custom metrics.
+    pub fn field_names(&self, scope: Scope) -> Vec<String> {
+        let mut names = FieldMap::builtin().names(scope);
+        names.extend(self.field_map.names(scope));
+        if scope == Scope::Game {
+            names.extend(
+                self.metrics
+                    .metrics
+                    .iter()
+                    .map(|metric| metric.name.clone()),
+            );
+        }
+        names
+    }
+
+    /// queries matching the schema of the database, with the fields it doesn't have including
+    /// the ones of the field map
+    pub fn query_set(&self) -> Result<QuerySet, DbError> {
+        let schema = AuroraSchema::detect(&self.open()?)?;
+        let mut queries = schema.query_set()?;
+        queries
+            .unavailable
+            .extend(self.field_map.unavailable(&schema));
+        Ok(queries)
+    }
+
+    /// games of the database without their races and populations
+    pub fn fetch_game_list(&self) -> Result<Vec<FCTGame>, DbError> {
+        let connection = self.open()?;
+        let queries = AuroraSchema::detect(&connection)?.query_set()?;
+        self.fetch_games(&connection, &queries)
+    }
+
+    /// Read a snapshot of the database. Values that couldn't be read and the errors of the user
+    /// defined metrics are returned with it, the snapshot is complete without them.
+    pub fn fetch(&self) -> Result<(AuroraData, Vec<DbError>), DbError> {
+        let connection = self.open()?;
+        let schema = AuroraSchema::detect(&connection)?;
+        let queries = schema.query_set()?;
+
+        let games = self.fetch_games(&connection, &queries)?;
 
         let mut games_data = vec![];
 
         for game in games {
-            let race = self.fetch_race(&connection, game.game_id);
-            let mut populations = self.fetch_populations(&connection, race.race_id);
+            let race_id = self.fetch_race_id(&connection, &queries, game.game_id)?;
+            let mut populations = self.fetch_populations(&connection, &queries, race_id)?;
 
             for pop in populations.iter_mut() {
-                let minerals = self.fetch_pop_minerals(
-                    &connection,
-                    game.game_id,
-                    pop.system_id.expect("system_id not returned"),
-                    pop.system_body_id.expect("system_body_id not returned"),
-                );
-
-                pop.minerals = Some(minerals);
+                pop.minerals = match (&queries.deposits, pop.system_id, pop.system_body_id) {
+                    (Some(sql), Some(system_id), Some(system_body_id)) => {
+                        Some(self.fetch_pop_minerals(
+                            &connection,
+                            sql,
+                            game.game_id,
+                            system_id,
+                            system_body_id,
+                        )?)
+                    }
+                    _ => None,
+                };
             }
 
             games_data.push(AuroraGameData {
                 game,
-                race_id: race.race_id,
+                race_id,
                 populations,
-                race: Some(race),
+                values: IndexMap::new(),
+                custom: IndexMap::new(),
             });
         }
 
-        Ok(AuroraData { games: games_data })
+        let mut data = AuroraData { games: games_data };
+        let mut errors = FieldMap::builtin().fetch(&connection, &schema, &mut data)?;
+        errors.extend(self.field_map.fetch(&connection, &schema, &mut data)?);
+        errors.extend(self.metrics.evaluate(&connection, &mut data));
+        Ok((data, errors))
     }
 
-    fn fetch_race(&self, connection: &Connection, game_id: u32) -> FCTRace {
-        let sql = r#"select RaceID, WealthPoints, AnnualWealth from FCT_Race where NPR = 0 and GameID = ?"#;
-        let mut stmt = connection.prepare(sql).unwrap();
-        stmt.query_row(&[&game_id], |row| FCTRace {
-            race_id: row.get(0),
-            wealth: row.get(1),
-            annual_wealth: row.get(2),
-        })
-        .unwrap()
+    /// player race of the game
+    fn fetch_race_id(
+        &self,
+        connection: &Connection,
+        queries: &QuerySet,
+        game_id: u32,
+    ) -> Result<u32, DbError> {
+        let mut stmt = connection.prepare(&queries.race).map_err(sql_error)?;
+        stmt.query_row(&[&game_id], |row| row.get(0))
+            .map_err(sql_error)
     }
 
     fn fetch_pop_minerals(
         &self,
         connection: &Connection,
+        sql: &str,
         game_id: u32,
         system_id: u32,
         system_body_id: u32,
-    ) -> Vec<FCTMineralDeposit> {
-        let sql = r#"select MaterialID, Amount, Accessibility from FCT_MineralDeposit where GameID = ? and SystemID = ? and SystemBodyID = ?"#;
-        let mut stmt = connection.prepare(sql).unwrap();
+    ) -> Result<Vec<FCTMineralDeposit>, DbError> {
+        let mut stmt = connection.prepare(sql).map_err(sql_error)?;
         stmt.query_map(&[&game_id, &system_id, &system_body_id], |row| {
             FCTMineralDeposit {
                 material_id: row.get(0),
@@ -162,9 +327,8 @@ impl AuroraDb {
                 acc: row.get(2),
             }
         })
-        .unwrap()
-        .collect::<Result<Vec<FCTMineralDeposit>, _>>()
-        .unwrap()
+        .and_then(|rows| rows.collect::<Result<Vec<FCTMineralDeposit>, _>>())
+        .map_err(sql_error)
     }
 
     // TODO: this should be probably in when reading db and converting to dashboard
@@ -228,47 +392,35 @@ impl AuroraDb {
     //         .collect()
     // }
 
-    fn fetch_populations(&self, connection: &Connection, race_id: u32) -> Vec<FCTPopulation> {
-        let sql = r#"select PopulationID, PopName, FuelStockpile, 
-                                  MaintenanceStockpile, Population, Duranium, 
-                                  Neutronium, Corbomite, Tritanium, Boronide, 
-                                  Mercassium, Vendarite, Sorium, Corundium,
-                                  Uridium,  Gallicite, SystemID, 
-                                  SystemBodyID
-                        from FCT_Population
-                        where RaceID = ?"#;
-        let mut stmt = connection.prepare(sql).unwrap();
+    fn fetch_populations(
+        &self,
+        connection: &Connection,
+        queries: &QuerySet,
+        race_id: u32,
+    ) -> Result<Vec<FCTPopulation>, DbError> {
+        let mut stmt = connection
+            .prepare(&queries.populations)
+            .map_err(sql_error)?;
         let populations: Vec<FCTPopulation> = stmt
             .query_map(&[&race_id], |row| FCTPopulation {
                 population_id: row.get(0),
                 pop_name: row.get(1),
-                fuel_stockpile: row.get(2),
-                maintenance_stockpile: row.get(3),
-                population: row.get(4),
-                duranium: row.get(5),
-                neutronium: row.get(6),
-                corbomite: row.get(7),
-                tritanium: row.get(8),
-                boronide: row.get(9),
-                mercassium: row.get(10),
-                vendarite: row.get(11),
-                sorium: row.get(12),
-                corundium: row.get(13),
-                uridium: row.get(14),
-                gallicite: row.get(15),
-                system_id: row.get(16),
-                system_body_id: row.get(17),
+                system_id: row.get(2),
+                system_body_id: row.get(3),
                 minerals: None,
+                values: IndexMap::new(),
             })
-            .unwrap()
-            .collect::<Result<Vec<_>, _>>()
-            .unwrap();
-        populations
+            .and_then(|rows| rows.collect::<Result<Vec<_>, _>>())
+            .map_err(sql_error)?;
+        Ok(populations)
     }
 
-    fn fetch_games(&self, connection: &Connection) -> Vec<FCTGame> {
-        let sql = r#"select GameID, GameName, GameTime, StartYear, LastViewed from FCT_Game where LastViewed = 1.0;"#;
-        let mut stmt = connection.prepare(sql).unwrap();
+    fn fetch_games(
+        &self,
+        connection: &Connection,
+        queries: &QuerySet,
+    ) -> Result<Vec<FCTGame>, DbError> {
+        let mut stmt = connection.prepare(&queries.games).map_err(sql_error)?;
         let games: Vec<FCTGame> = stmt
             .query_map(&[], |row| FCTGame {
                 game_id: row.get(0),
@@ -277,9 +429,119 @@ impl AuroraDb {
                 start_year: row.get(3),
                 last_viewed: row.get(4),
             })
+            .and_then(|rows| rows.collect::<Result<Vec<_>, _>>())
+            .map_err(sql_error)?;
+        Ok(games)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+
+    /// Aurora database at `path` with a single game, race and population
+    pub(crate) fn create_db(path: &Path, game_time: f64, population: f64) {
+        let _ = std::fs::remove_file(path);
+        let connection = Connection::open(path).unwrap();
+        connection
+            .execute_batch(&format!(
+                "create table FCT_Game (GameID, GameName, GameTime, StartYear, LastViewed);
+                 insert into FCT_Game values (1, 'Sol Campaign', {game_time}, 2025, 1.0);
+                 create table FCT_Race (RaceID, GameID, NPR, WealthPoints, AnnualWealth);
+                 insert into FCT_Race values (4, 1, 0, 1000, 50);
+                 create table FCT_Population (PopulationID, RaceID, PopName, FuelStockpile,
+                    MaintenanceStockpile, Population, Duranium, Neutronium, Corbomite, Tritanium,
+                    Boronide, Mercassium, Vendarite, Sorium, Corundium, Uridium, Gallicite,
+                    SystemID, SystemBodyID);
+                 insert into FCT_Population values (7, 4, 'Earth', 100, 10, {population}, 1, 2,
+                    3, 4, 5, 6, 7, 8, 9, 10, 11, 1, 3);
+                 create table FCT_MineralDeposit (GameID, SystemID, SystemBodyID, MaterialID,
+                    Amount, Accessibility);
+                 insert into FCT_MineralDeposit values (1, 1, 3, 1, 5000, 0.5);"
+            ))
+            .unwrap();
+    }
+
+    #[test]
+    fn aurora_db_should_fetch_snapshot() {
+        let path =
+            std::env::temp_dir().join(format!("aurora_analytics_{}_fetch.db", std::process::id()));
+        create_db(&path, 86400.0, 800.0);
+
+        let (data, errors) = AuroraDb::new(&path).fetch().unwrap();
+        assert!(errors.is_empty());
+        assert_eq!(data.games.len(), 1);
+        let agame = &data.games[0];
+        assert_eq!(agame.game.game_name, "Sol Campaign");
+        assert_eq!(agame.race_id, 4);
+        assert_eq!(agame.populations[0].pop_name, "Earth");
+        assert_eq!(agame.values.get("wealth"), Some(&1000.0));
+        assert_eq!(agame.populations[0].values.get("population"), Some(&800.0));
+        assert_eq!(agame.populations[0].values.len(), 14);
+        assert_eq!(agame.populations[0].minerals.as_ref().unwrap().len(), 1);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn aurora_db_should_skip_fields_of_missing_columns() {
+        let path =
+            std::env::temp_dir().join(format!("aurora_analytics_{}_legacy.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        Connection::open(&path)
             .unwrap()
-            .collect::<Result<Vec<_>, _>>()
+            .execute_batch(
+                "create table FCT_Game (GameID, GameName, GameTime, StartYear, LastViewed);
+                 insert into FCT_Game values (1, 'Old Campaign', 86400, 2025, 1.0);
+                 create table FCT_Race (RaceID, GameID, NPR, WealthPoints);
+                 insert into FCT_Race values (4, 1, 0, 1000);
+                 create table FCT_Population (PopulationID, RaceID, PopName, Population);
+                 insert into FCT_Population values (7, 4, 'Earth', 800);",
+            )
             .unwrap();
-        games
+
+        let aurora_db = AuroraDb::new(&path);
+        let unavailable = aurora_db.query_set().unwrap().unavailable;
+        assert!(unavailable.contains(&"FCT_Race.AnnualWealth".to_string()));
+        assert!(unavailable.contains(&"FCT_Population.Gallicite".to_string()));
+
+        let (data, errors) = aurora_db.fetch().unwrap();
+        assert!(errors.is_empty());
+        let agame = &data.games[0];
+        assert_eq!(agame.values.get("wealth"), Some(&1000.0));
+        assert_eq!(agame.values.get("annual_wealth"), None);
+        let pop = &agame.populations[0];
+        assert_eq!(pop.values.get("population"), Some(&800.0));
+        assert_eq!(pop.values.len(), 1);
+        assert!(pop.minerals.is_none());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn aurora_data_should_read_snapshots_of_older_versions() {
+        let agame: AuroraGameData = serde_json::from_str(
+            r#"{
+                "game": {"game_id": 1, "game_name": "Sol", "game_time": 1.0, "start_year": 2025,
+                         "last_viewed": 1.0},
+                "race_id": 4,
+                "race": {"race_id": 4, "wealth": 40.0, "annual_wealth": 10.0},
+                "populations": [{
+                    "population_id": 7, "system_id": null, "system_body_id": null,
+                    "pop_name": "Earth", "fuel_stockpile": 1.0, "population": 3.0,
+                    "uridium": null, "minerals": null
+                }]
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(agame.values.get("wealth"), Some(&40.0));
+        assert_eq!(agame.values.get("annual_wealth"), Some(&10.0));
+        assert_eq!(agame.values.len(), 2);
+        let pop = &agame.populations[0];
+        assert_eq!(pop.pop_name, "Earth");
+        assert_eq!(pop.values.get("population"), Some(&3.0));
+        assert_eq!(pop.values.get("uridium"), None);
+        assert_eq!(pop.values.len(), 2);
     }
 }
@@ -0,0 +1,267 @@
+use crate::aurora_db::DbError;
+use crate::field_map::FieldMap;
+use rusqlite::Connection;
+use std::collections::HashMap;
+
+/// What to select when a column doesn't exist in the database
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Fallback {
+    /// the query can't be done without it
+    Required,
+    /// read as `NULL`, for fields that are `Option`
+    Null,
+}
+
+use self::Fallback::*;
+
+const GAME_COLUMNS: &[(&str, Fallback)] = &[
+    ("GameID", Required),
+    ("GameName", Required),
+    ("GameTime", Required),
+    ("StartYear", Required),
+    ("LastViewed", Required),
+];
+
+const RACE_COLUMNS: &[(&str, Fallback)] = &[("RaceID", Required)];
+const RACE_FILTER: &[&str] = &["NPR", "GameID"];
+
+const POPULATION_COLUMNS: &[(&str, Fallback)] = &[
+    ("PopulationID", Required),
+    ("PopName", Required),
+    ("SystemID", Null),
+    ("SystemBodyID", Null),
+];
+const POPULATION_FILTER: &[&str] = &["RaceID"];
+
+const DEPOSIT_COLUMNS: &[(&str, Fallback)] = &[
+    ("MaterialID", Required),
+    ("Amount", Required),
+    ("Accessibility", Required),
+];
+const DEPOSIT_FILTER: &[&str] = &["GameID", "SystemID", "SystemBodyID"];
+
+/// Tables and columns of an Aurora database
+#[derive(Debug, Clone, Default)]
+pub struct AuroraSchema {
+    tables: HashMap<String, Vec<String>>,
+}
+
+/// Queries built from the columns the database has, missing optional columns are read as `NULL`
+/// instead of failing, so older Aurora databases work with fewer fields
+#[derive(Debug, Clone)]
+pub struct QuerySet {
+    pub games: String,
+    pub race: String,
+    pub populations: String,
+    /// `None` when mineral deposits can't be read
+    pub deposits: Option<String>,
+    /// `table.column` read with a fallback value or skipped, including the built-in fields
+    pub unavailable: Vec<String>,
+}
+
+impl AuroraSchema {
+    pub fn detect(connection: &Connection) -> Result<Self, DbError> {
+        let sql = "select name from sqlite_master where type = 'table'";
+        let mut stmt = connection.prepare(sql).map_err(schema_error)?;
+        let names: Vec<String> = stmt
+            .query_map(&[], |row| row.get(0))
+            .and_then(|rows| rows.collect::<Result<Vec<_>, _>>())
+            .map_err(schema_error)?;
+
+        let mut tables = HashMap::new();
+        for name in names {
+            let sql = format!("PRAGMA table_info(\"{}\")", name.replace('"', "\"\""));
+            let mut stmt = connection.prepare(&sql).map_err(schema_error)?;
+            let columns: Vec<String> = stmt
+                .query_map(&[], |row| row.get(1))
+                .and_then(|rows| rows.collect::<Result<Vec<_>, _>>())
+                .map_err(schema_error)?;
+            tables.insert(name, columns);
+        }
+
+        Ok(AuroraSchema { tables })
+    }
+
+    pub fn has_table(&self, table: &str) -> bool {
+        self.tables.contains_key(table)
+    }
+
+    pub fn has_column(&self, table: &str, column: &str) -> bool {
+        self.tables
+            .get(table)
+            .map(|columns| columns.iter().any(|c| c.eq_ignore_ascii_case(column)))
+            .unwrap_or(false)
+    }
+
+    /// build the queries for this schema, fails when a required table or column is missing
+    pub fn query_set(&self) -> Result<QuerySet, DbError> {
+        let mut unavailable = vec![];
+        let mut missing = vec![];
+
+        let games = self.select(
+            "FCT_Game",
+            GAME_COLUMNS,
+            &[],
+            &mut unavailable,
+            &mut missing,
+        );
+        let race = self.select(
+            "FCT_Race",
+            RACE_COLUMNS,
+            RACE_FILTER,
+            &mut unavailable,
+            &mut missing,
+        );
+        let populations = self.select(
+            "FCT_Population",
+            POPULATION_COLUMNS,
+            POPULATION_FILTER,
+            &mut unavailable,
+            &mut missing,
+        );
+
+        if !missing.is_empty() {
+            return Err(DbError::Generic(format!(
+                "unsupported Aurora database, missing {}",
+                missing.join(", ")
+            )));
+        }
+
+        // deposits are optional as a whole
+        let mut deposit_missing = vec![];
+        let deposits = self.select(
+            "FCT_MineralDeposit",
+            DEPOSIT_COLUMNS,
+            DEPOSIT_FILTER,
+            &mut unavailable,
+            &mut deposit_missing,
+        );
+        let deposits = if deposit_missing.is_empty() {
+            Some(deposits)
+        } else {
+            unavailable.extend(deposit_missing);
+            None
+        };
+
+        unavailable.extend(FieldMap::builtin().unavailable(self));
+
+        Ok(QuerySet {
+            games: format!("{} where LastViewed = 1.0", games),
+            race: format!("{} where NPR = 0 and GameID = ?", race),
+            populations: format!("{} where RaceID = ?", populations),
+            deposits: deposits.map(|sql| {
+                format!(
+                    "{} where GameID = ? and SystemID = ? and SystemBodyID = ?",
+                    sql
+                )
+            }),
+            unavailable,
+        })
+    }
+
+    /// `select` of `columns` from `table`, `filter` columns are only checked as they are used by
+    /// the where clause
+    fn select(
+        &self,
+        table: &str,
+        columns: &[(&str, Fallback)],
+        filter: &[&str],
+        unavailable: &mut Vec<String>,
+        missing: &mut Vec<String>,
+    ) -> String {
+        if !self.has_table(table) {
+            missing.push(table.to_string());
+            return String::new();
+        }
+
+        let selected: Vec<String> = columns
+            .iter()
+            .map(|(column, fallback)| {
+                if self.has_column(table, column) {
+                    return column.to_string();
+                }
+
+                let name = format!("{}.{}", table, column);
+                match fallback {
+                    Required => {
+                        missing.push(name);
+                        column.to_string()
+                    }
+                    Null => {
+                        unavailable.push(name);
+                        format!("NULL as {}", column)
+                    }
+                }
+            })
+            .collect();
+
+        for column in filter {
+            if !self.has_column(table, column) {
+                missing.push(format!("{}.{}", table, column));
+            }
+        }
+
+        format!("select {} from {}", selected.join(", "), table)
+    }
+}
+
+fn schema_error(e: rusqlite::Error) -> DbError {
+    DbError::Generic(format!("fail to read the database schema: {}", e))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn connection(sql: &str) -> Connection {
+        let connection = Connection::open_in_memory().unwrap();
+        connection.execute_batch(sql).unwrap();
+        connection
+    }
+
+    #[test]
+    fn schema_should_replace_missing_columns() {
+        let connection = connection(
+            "create table FCT_Game (GameID, GameName, GameTime, StartYear, LastViewed);
+             create table FCT_Race (RaceID, GameID, NPR, WealthPoints);
+             create table FCT_Population (PopulationID, RaceID, PopName, Population);",
+        );
+
+        let queries = AuroraSchema::detect(&connection)
+            .unwrap()
+            .query_set()
+            .unwrap();
+
+        assert!(queries.populations.contains("NULL as SystemID"));
+        assert!(queries
+            .unavailable
+            .contains(&"FCT_Race.AnnualWealth".to_string()));
+        assert!(queries.deposits.is_none());
+        assert!(queries
+            .unavailable
+            .contains(&"FCT_Population.Gallicite".to_string()));
+        assert!(queries
+            .unavailable
+            .contains(&"FCT_MineralDeposit".to_string()));
+
+        // the generated queries are valid for the schema
+        connection.prepare(&queries.populations).unwrap();
+        connection.prepare(&queries.race).unwrap();
+    }
+
+    #[test]
+    fn schema_should_fail_on_missing_required_columns() {
+        let connection = connection(
+            "create table FCT_Game (GameID, GameName, GameTime, StartYear, LastViewed);
+             create table FCT_Race (GameID, NPR);",
+        );
+
+        match AuroraSchema::detect(&connection).unwrap().query_set() {
+            Err(DbError::Generic(msg)) => {
+                assert!(msg.contains("FCT_Race.RaceID"));
+                assert!(msg.contains("FCT_Population"));
+            }
+            Ok(_) => panic!("query set should fail"),
+        }
+    }
+}
@@ -0,0 +1,122 @@
+use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime, NaiveTime, Timelike};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Seconds of a aurora year, used when only a approximated elapsed time is required
+pub const SECONDS_PER_YEAR: f64 = 60.0 * 60.0 * 24.0 * 365.0;
+
+/// A date in the game calendar.
+///
+/// Aurora stores the game time as seconds elapsed since the 1st of january of the game start
+/// year and uses the gregorian calendar, so leap years are respected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct GameDate {
+    pub year: i32,
+    pub month: u32,
+    pub day: u32,
+    pub hour: u32,
+    pub minute: u32,
+    pub second: u32,
+}
+
+impl GameDate {
+    /// Date `game_time` seconds after the start of `start_year`. Dates beyond the range chrono
+    /// can represent, from a corrupted database, are clamped to the first or last one.
+    pub fn from_game_time(start_year: u32, game_time: f64) -> Self {
+        let start_year = start_year.min(NaiveDate::MAX.year() as u32) as i32;
+        let start = NaiveDate::from_ymd_opt(start_year, 1, 1)
+            .unwrap_or(NaiveDate::MAX)
+            .and_time(NaiveTime::MIN);
+        let date = Duration::try_seconds(game_time as i64)
+            .and_then(|elapsed| start.checked_add_signed(elapsed))
+            .unwrap_or(if game_time < 0.0 {
+                NaiveDateTime::MIN
+            } else {
+                NaiveDateTime::MAX
+            });
+        GameDate::from(date)
+    }
+
+    /// date formatted as `YYYY-MM-DD`
+    pub fn to_iso_date(&self) -> String {
+        format!("{:04}-{:02}-{:02}", self.year, self.month, self.day)
+    }
+
+    /// date and time formatted as `YYYY-MM-DDTHH:MM:SS`
+    pub fn to_iso_datetime(&self) -> String {
+        format!(
+            "{}T{:02}:{:02}:{:02}",
+            self.to_iso_date(),
+            self.hour,
+            self.minute,
+            self.second
+        )
+    }
+}
+
+impl From<NaiveDateTime> for GameDate {
+    fn from(date: NaiveDateTime) -> Self {
+        GameDate {
+            year: date.year(),
+            month: date.month(),
+            day: date.day(),
+            hour: date.hour(),
+            minute: date.minute(),
+            second: date.second(),
+        }
+    }
+}
+
+impl fmt::Display for GameDate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.to_iso_date())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_game_date_at_start() {
+        let date = GameDate::from_game_time(2025, 0.0);
+        assert_eq!(date.to_iso_datetime(), "2025-01-01T00:00:00");
+    }
+
+    #[test]
+    fn test_fctgame_month() {
+        let date = GameDate::from_game_time(2025, 454_853_030.0);
+        assert_eq!(date.year, 2039);
+        assert_eq!(date.month, 6);
+        assert_eq!(date.day, 1);
+        assert_eq!(date.to_iso_date(), "2039-06-01");
+        assert_eq!(date.to_iso_datetime(), "2039-06-01T12:03:50");
+    }
+
+    #[test]
+    fn test_game_date_respect_leap_years() {
+        // 2028 is a leap year, 59 days after start is 29 of february
+        let date = GameDate::from_game_time(2028, 59.0 * 24.0 * 60.0 * 60.0);
+        assert_eq!(date.to_iso_date(), "2028-02-29");
+    }
+
+    #[test]
+    fn test_game_date_clamps_out_of_range_times() {
+        let last = GameDate::from(NaiveDateTime::MAX);
+        assert_eq!(GameDate::from_game_time(2025, 1e300), last);
+        assert_eq!(GameDate::from_game_time(2025, f64::INFINITY), last);
+        assert_eq!(
+            GameDate::from_game_time(u32::MAX, 0.0).year,
+            NaiveDate::MAX.year()
+        );
+        assert_eq!(GameDate::from_game_time(u32::MAX, 1e12), last);
+        assert_eq!(
+            GameDate::from_game_time(2025, -1e300),
+            GameDate::from(NaiveDateTime::MIN)
+        );
+        assert_eq!(
+            GameDate::from_game_time(2025, f64::NAN).to_iso_date(),
+            "2025-01-01"
+        );
+    }
+}
@@ -1,55 +1,383 @@
+use crate::alerts::{Alert, AlertRule};
+use crate::archive::{self, ArchiveCfg};
 use crate::aurora_db::*;
-use crate::dashboard::DashboardDb;
+use crate::dashboard::{self, Dashboard, DashboardDb, DerivedField, SharedDashboard};
+use crate::dump::{DumpError, DumpReader, DumpWriter};
+use crate::field_map::Scope;
+use crate::hooks::{HookCfg, HookEvent};
 use notify::{watcher, DebouncedEvent, RecursiveMode, Watcher};
-use std::fs::OpenOptions;
-use std::io::prelude::*;
+use serde::Serialize;
+use std::cell::RefCell;
+use std::collections::HashSet;
 use std::path::PathBuf;
 use std::sync::mpsc::channel;
 use std::thread::sleep;
 use std::time::Duration;
 
+/// Notification of a new snapshot appended into the dashboard
+#[derive(Clone, Debug, Serialize)]
+pub struct SnapshotEvent {
+    pub game_id: u32,
+    pub game_time: f64,
+    /// game calendar date in `YYYY-MM-DDTHH:MM:SS` format
+    pub date: String,
+    /// populations with new or changed values
+    pub populations: Vec<u32>,
+}
+
 #[derive(Clone, Debug)]
 pub struct CollectorCfg {
-    pub db_path: String,
+    /// Aurora database, with the field map and metrics to read
+    pub aurora_db: AuroraDb,
     pub dump_path: String,
     pub dashboard_path: String,
+    pub keyframe_interval: usize,
+    /// copy the database into the archive on every save when present
+    pub archive: Option<ArchiveCfg>,
+    /// fields computed after every snapshot
+    pub derived: Vec<DerivedField>,
+    /// rules checked after every snapshot, new alerts are printed
+    pub alerts: Vec<AlertRule>,
+    /// notified of every snapshot and alert
+    pub hooks: HookCfg,
 }
 
 #[derive(Debug)]
-pub enum CollectorError {}
+pub enum CollectorError {
+    Aurora(DbError),
+    Dashboard(dashboard::DbError),
+    Dump(DumpError),
+    Generic(String),
+}
+
+impl From<DbError> for CollectorError {
+    fn from(e: DbError) -> Self {
+        CollectorError::Aurora(e)
+    }
+}
+
+impl From<DumpError> for CollectorError {
+    fn from(e: DumpError) -> Self {
+        CollectorError::Dump(e)
+    }
+}
+
+impl From<dashboard::DbError> for CollectorError {
+    fn from(e: dashboard::DbError) -> Self {
+        CollectorError::Dashboard(e)
+    }
+}
 
 pub fn collect(cfg: CollectorCfg) -> Result<(), CollectorError> {
-    watch(&cfg.db_path, || {
-        append_output(&cfg.db_path, &cfg.dump_path).unwrap();
-        convert_into_dashboard(&cfg.dump_path, &cfg.dashboard_path).unwrap();
+    log_unavailable(&cfg.aurora_db);
+    log_unknown_alert_fields(&cfg);
+    let writer = RefCell::new(DumpWriter::append(&cfg.dump_path, cfg.keyframe_interval)?);
+
+    let db_path = cfg.aurora_db.path().to_string_lossy().to_string();
+
+    watch(&db_path, || {
+        let result = append_output(&cfg.aurora_db, &mut writer.borrow_mut()).and_then(|data| {
+            archive_output(&cfg, &data);
+            let dashboard = convert_into_dashboard(
+                &cfg.dump_path,
+                &cfg.dashboard_path,
+                &cfg.derived,
+                &cfg.alerts,
+            )?;
+            let (snapshots, alerts) = new_events(&dashboard, &data);
+            publish(&cfg, snapshots, alerts, |_| {});
+            Ok(())
+        });
+
+        if let Err(e) = result {
+            println!("fail to collect {}: {:?}", db_path, e);
+        }
     })
 }
 
-pub fn append_output(db_path: &str, output_path: &str) -> Result<(), CollectorError> {
-    let aurora_db = AuroraDb::new(&PathBuf::from(db_path));
-    let data = aurora_db.fetch().unwrap();
+/// Watch the Aurora database and append every new snapshot into the in memory dashboard, the
+/// dashboard file is still updated so it can be served later without the collector.
+///
+/// `listener` is called for each game of every appended snapshot.
+pub fn collect_into<F>(
+    cfg: CollectorCfg,
+    dashboard: SharedDashboard,
+    listener: F,
+) -> Result<(), CollectorError>
+where
+    F: Fn(SnapshotEvent),
+{
+    log_unavailable(&cfg.aurora_db);
+    log_unknown_alert_fields(&cfg);
+    let writer = RefCell::new(DumpWriter::append(&cfg.dump_path, cfg.keyframe_interval)?);
 
-    let json = serde_json::to_string(&data).unwrap();
+    let db_path = cfg.aurora_db.path().to_string_lossy().to_string();
 
-    {
-        let mut file = OpenOptions::new()
-            .append(true)
-            .create(true)
-            .open(output_path)
-            .unwrap();
+    watch(&db_path, || {
+        if let Err(e) = collect_snapshot(&cfg, &mut writer.borrow_mut(), &dashboard, &listener) {
+            println!("fail to collect {}: {:?}", db_path, e);
+        }
+    })
+}
 
-        writeln!(file, "{}", json).unwrap();
-    }
+/// Append a new snapshot into the dump and the shared dashboard, then publish its events
+fn collect_snapshot<F>(
+    cfg: &CollectorCfg,
+    writer: &mut DumpWriter,
+    dashboard: &SharedDashboard,
+    listener: &F,
+) -> Result<(), CollectorError>
+where
+    F: Fn(SnapshotEvent),
+{
+    let data = append_output(&cfg.aurora_db, writer)?;
+    archive_output(cfg, &data);
 
-    println!("data updated at {}", output_path);
+    let updated = {
+        let mut dashboard = dashboard
+            .write()
+            .map_err(|_| CollectorError::Generic("dashboard lock poisoned".to_string()))?;
+        dashboard.append(&data);
+        dashboard.derive(&cfg.derived);
+        dashboard.evaluate_alerts(&cfg.alerts);
+        dashboard.clone()
+    };
 
+    // writing the file and the hooks can be slow, don't keep the web server waiting
+    DashboardDb::save(&updated, &cfg.dashboard_path)?;
+    let (snapshots, alerts) = new_events(&updated, &data);
+    publish(cfg, snapshots, alerts, listener);
     Ok(())
 }
 
-pub fn convert_into_dashboard(dump_path: &str, dashboard_path: &str) -> Result<(), CollectorError> {
-    let dashboard = DashboardDb::load_aurora_dump(dump_path).unwrap();
-    DashboardDb::save(&dashboard, dashboard_path).unwrap();
-    Ok(())
+/// Fetch a snapshot of the Aurora database and append it into the dump
+pub fn append_output(
+    aurora_db: &AuroraDb,
+    writer: &mut DumpWriter,
+) -> Result<AuroraData, CollectorError> {
+    let (data, errors) = aurora_db.fetch()?;
+    log_fetch_errors(aurora_db, &errors);
+
+    writer.write(&data)?;
+
+    println!("data updated from {}", aurora_db.path().display());
+
+    Ok(data)
+}
+
+/// the fields the database doesn't have are reported once, they are skipped on every snapshot
+fn log_unavailable(aurora_db: &AuroraDb) {
+    let path = aurora_db.path().display();
+    match aurora_db.query_set() {
+        Ok(queries) if !queries.unavailable.is_empty() => println!(
+            "{} doesn't have every column, skipping {}",
+            path,
+            queries.unavailable.join(", ")
+        ),
+        Ok(_) => {}
+        Err(e) => println!("fail to read the schema of {}: {:?}", path, e),
+    }
+}
+
+/// a rule on a field that is neither read nor derived never raises anything, usually a typo
+fn log_unknown_alert_fields(cfg: &CollectorCfg) {
+    let derived = |scope| {
+        cfg.derived
+            .iter()
+            .filter(move |field| field.scope == scope)
+            .map(|field| field.name.clone())
+    };
+
+    let mut population = cfg.aurora_db.field_names(Scope::Population);
+    let mut game = cfg.aurora_db.field_names(Scope::Game);
+    // empire totals
+    game.extend(population.iter().cloned());
+    game.extend(derived(Scope::Game));
+    population.extend(derived(Scope::Population));
+
+    for rule in &cfg.alerts {
+        let (scope, names) = match rule.scope {
+            Scope::Game => ("game", &game),
+            Scope::Population => ("population", &population),
+        };
+        if !rule.is_known(names) {
+            println!(
+                "alert on {} never raised, no {} field has this name",
+                rule.field, scope
+            );
+        }
+    }
+}
+
+/// values that couldn't be read don't stop the snapshot, they are only reported
+fn log_fetch_errors(aurora_db: &AuroraDb, errors: &[DbError]) {
+    for e in errors {
+        println!("skipping value of {}: {:?}", aurora_db.path().display(), e);
+    }
+}
+
+/// Result of a backfill
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BackfillStats {
+    pub databases: usize,
+    pub appended: usize,
+    /// snapshots with game times already in the dump
+    pub skipped: usize,
+    /// databases that couldn't be read, with the reason
+    pub failed: Vec<(String, String)>,
+}
+
+/// Fetch every `.db` file of `dir` and append the snapshots into the dump ordered by game time.
+///
+/// Games with a game time already present in the dump are ignored so a folder can be imported
+/// more than once. `aurora_db` provides the field map and metrics, each file is read with
+/// `AuroraDb::at`. The dashboard must be rebuilt afterwards.
+pub fn backfill(
+    dir: &str,
+    aurora_db: &AuroraDb,
+    writer: &mut DumpWriter,
+    dump_path: &str,
+) -> Result<BackfillStats, CollectorError> {
+    let mut stats = BackfillStats::default();
+
+    let mut known: HashSet<(u32, u64)> = HashSet::new();
+    if std::path::Path::new(dump_path).exists() {
+        for data in DumpReader::open(dump_path)? {
+            for agame in data?.games {
+                known.insert((agame.game.game_id, agame.game.game_time.to_bits()));
+            }
+        }
+    }
+
+    let mut paths: Vec<PathBuf> = std::fs::read_dir(dir)
+        .map_err(|e| CollectorError::Generic(format!("fail to read {}: {}", dir, e)))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.is_file() && path.extension().and_then(|e| e.to_str()) == Some("db"))
+        .collect();
+    paths.sort();
+
+    // only the game times are read first, so a single snapshot is in memory at a time
+    let mut databases = vec![];
+    for path in paths {
+        stats.databases += 1;
+        match aurora_db.at(&path).fetch_game_list() {
+            Ok(games) => {
+                let game_time = games
+                    .iter()
+                    .map(|game| game.game_time)
+                    .fold(f64::NEG_INFINITY, f64::max);
+                databases.push((game_time, path));
+            }
+            Err(e) => stats
+                .failed
+                .push((path.display().to_string(), format!("{:?}", e))),
+        }
+    }
+    databases.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    for (_, path) in databases {
+        let aurora_db = aurora_db.at(&path);
+        let mut data = match aurora_db.fetch() {
+            Ok((data, errors)) => {
+                log_fetch_errors(&aurora_db, &errors);
+                data
+            }
+            Err(e) => {
+                stats
+                    .failed
+                    .push((path.display().to_string(), format!("{:?}", e)));
+                continue;
+            }
+        };
+
+        data.games
+            .retain(|agame| known.insert((agame.game.game_id, agame.game.game_time.to_bits())));
+
+        if data.games.is_empty() {
+            stats.skipped += 1;
+        } else {
+            writer.write(&data)?;
+            stats.appended += 1;
+        }
+    }
+
+    Ok(stats)
+}
+
+/// copy the database into the archive when enabled, failures are only logged as the snapshot is
+/// already in the dump
+fn archive_output(cfg: &CollectorCfg, data: &AuroraData) {
+    if let Some(archive) = &cfg.archive {
+        let db_path = cfg.aurora_db.path().to_string_lossy().to_string();
+        match archive::archive_db(archive, &db_path, data) {
+            Ok(path) => println!("database archived at {}", path.display()),
+            Err(e) => println!("fail to archive {}: {:?}", db_path, e),
+        }
+    }
+}
+
+/// rebuild the dashboard file from the dump, with the `derived` fields and the `alerts`
+pub fn convert_into_dashboard(
+    dump_path: &str,
+    dashboard_path: &str,
+    derived: &[DerivedField],
+    alerts: &[AlertRule],
+) -> Result<Dashboard, CollectorError> {
+    let mut dashboard = DashboardDb::load_aurora_dump(dump_path)?;
+    dashboard.derive(derived);
+    dashboard.evaluate_alerts(alerts);
+    DashboardDb::save(&dashboard, dashboard_path)?;
+    Ok(dashboard)
+}
+
+/// snapshot events and alerts raised by the snapshot `data`
+fn new_events(dashboard: &Dashboard, data: &AuroraData) -> (Vec<SnapshotEvent>, Vec<Alert>) {
+    let mut snapshots = vec![];
+    let mut alerts = vec![];
+
+    for agame in &data.games {
+        let game_id = agame.game.game_id;
+        let game_time = agame.game.game_time;
+
+        if let Some(game) = dashboard.games.iter().find(|g| g.game_id == game_id) {
+            snapshots.push(SnapshotEvent {
+                game_id,
+                game_time,
+                date: game.date(game_time).to_iso_datetime(),
+                populations: game.changed_populations(game_time),
+            });
+            alerts.extend(
+                game.alerts
+                    .iter()
+                    .filter(|alert| alert.game_time == game_time)
+                    .cloned(),
+            );
+        }
+    }
+
+    (snapshots, alerts)
+}
+
+/// print the alerts and send every event to the `listener` and the hooks
+fn publish<F>(cfg: &CollectorCfg, snapshots: Vec<SnapshotEvent>, alerts: Vec<Alert>, listener: F)
+where
+    F: Fn(SnapshotEvent),
+{
+    let mut events = vec![];
+    for snapshot in snapshots {
+        events.push(HookEvent::Snapshot(snapshot.clone()));
+        listener(snapshot);
+    }
+    for alert in alerts {
+        println!("alert: {}", alert);
+        events.push(HookEvent::Alert(alert));
+    }
+
+    for event in &events {
+        for e in cfg.hooks.notify(event) {
+            println!("hook failed: {:?}", e);
+        }
+    }
 }
 
 fn watch<F>(db_path: &str, callback: F) -> Result<(), CollectorError>
@@ -61,11 +389,14 @@ where
 
     // Create a watcher object, delivering debounced events.
     // The notification back-end is selected based on the platform.
-    let mut watcher = watcher(tx, Duration::from_secs(10)).unwrap();
+    let mut watcher = watcher(tx, Duration::from_secs(10))
+        .map_err(|e| CollectorError::Generic(format!("fail to create watcher: {:?}", e)))?;
 
     // Add a path to be watched. All files and directories at that path and
     // below will be monitored for changes.
-    watcher.watch(db_path, RecursiveMode::Recursive).unwrap();
+    watcher
+        .watch(db_path, RecursiveMode::Recursive)
+        .map_err(|e| CollectorError::Generic(format!("fail to watch {}: {:?}", db_path, e)))?;
 
     loop {
         match rx.recv() {
@@ -75,7 +406,137 @@ where
                 callback()
             }
             Ok(_) => {}
-            Err(e) => println!("watch error: {:?}", e),
+            Err(e) => return Err(CollectorError::Generic(format!("watch error: {:?}", e))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::aurora_db::test::create_db;
+    use std::path::Path;
+    use std::sync::{Arc, Mutex, RwLock};
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("aurora_analytics_{}_{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        path
+    }
+
+    fn collector_cfg(dir: &Path) -> CollectorCfg {
+        let path = |name: &str| dir.join(name).to_string_lossy().to_string();
+        CollectorCfg {
+            aurora_db: AuroraDb::new(&dir.join("AuroraDB.db")),
+            dump_path: path("dump.json"),
+            dashboard_path: path("dashboard.json"),
+            keyframe_interval: 5,
+            archive: None,
+            derived: vec![],
+            alerts: serde_json::from_str(r#"[{"field": "population", "above": 1000}]"#).unwrap(),
+            hooks: HookCfg::default(),
         }
     }
+
+    #[test]
+    fn collector_should_append_snapshots_into_shared_dashboard() {
+        let dir = temp_dir("collect_into");
+        let cfg = collector_cfg(&dir);
+        let dashboard: SharedDashboard = Arc::new(RwLock::new(Dashboard::new()));
+        let mut writer = DumpWriter::append(&cfg.dump_path, cfg.keyframe_interval).unwrap();
+        let events = Mutex::new(vec![]);
+        let listener = |event| events.lock().unwrap().push(event);
+
+        create_db(cfg.aurora_db.path(), 86400.0, 800.0);
+        collect_snapshot(&cfg, &mut writer, &dashboard, &listener).unwrap();
+        create_db(cfg.aurora_db.path(), 2.0 * 86400.0, 1200.0);
+        collect_snapshot(&cfg, &mut writer, &dashboard, &listener).unwrap();
+
+        let events = events.into_inner().unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[1].game_id, 1);
+        assert_eq!(events[1].date, "2025-01-03T00:00:00");
+        assert_eq!(events[1].populations, vec![7]);
+
+        let shared = dashboard.read().unwrap();
+        let game = &shared.games[0];
+        let population = &game.populations[0].get_field("population").historical;
+        assert_eq!(population.len(), 2);
+        assert_eq!(population[1].y, 1200.0);
+        assert_eq!(game.alerts.len(), 1);
+
+        // the dashboard file and the dump follow the shared dashboard
+        let saved = DashboardDb::load(&cfg.dashboard_path).unwrap();
+        assert_eq!(
+            saved.games[0].populations[0].fields.len(),
+            game.populations[0].fields.len()
+        );
+        assert_eq!(DumpReader::open(&cfg.dump_path).unwrap().count(), 2);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn backfill_should_append_databases_by_game_time_once() {
+        let dir = temp_dir("backfill");
+        let backups = dir.join("backups");
+        std::fs::create_dir_all(&backups).unwrap();
+        // names and game times in opposite order
+        create_db(&backups.join("a.db"), 2.0 * 86400.0, 900.0);
+        create_db(&backups.join("b.db"), 86400.0, 800.0);
+        std::fs::write(backups.join("notes.txt"), "not a database").unwrap();
+
+        let cfg = collector_cfg(&dir);
+        let backups = backups.to_string_lossy().to_string();
+        let mut writer = DumpWriter::append(&cfg.dump_path, cfg.keyframe_interval).unwrap();
+
+        let stats = backfill(&backups, &cfg.aurora_db, &mut writer, &cfg.dump_path).unwrap();
+        assert_eq!(
+            stats,
+            BackfillStats {
+                databases: 2,
+                appended: 2,
+                skipped: 0,
+                failed: vec![],
+            }
+        );
+        let times: Vec<f64> = DumpReader::open(&cfg.dump_path)
+            .unwrap()
+            .map(|data| data.unwrap().games[0].game.game_time)
+            .collect();
+        assert_eq!(times, vec![86400.0, 2.0 * 86400.0]);
+
+        // importing the same folder again doesn't change the dump
+        let stats = backfill(&backups, &cfg.aurora_db, &mut writer, &cfg.dump_path).unwrap();
+        assert_eq!((stats.appended, stats.skipped), (0, 2));
+        assert_eq!(DumpReader::open(&cfg.dump_path).unwrap().count(), 2);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn collector_should_keep_collecting_when_archive_fails() {
+        let dir = temp_dir("archive_failure");
+        let mut cfg = collector_cfg(&dir);
+        // a file where the archive folder should be
+        let archive_dir = dir.join("archive");
+        std::fs::write(&archive_dir, "").unwrap();
+        cfg.archive = Some(ArchiveCfg {
+            dir: archive_dir.to_string_lossy().to_string(),
+            max_count: None,
+            max_size: None,
+        });
+        let dashboard: SharedDashboard = Arc::new(RwLock::new(Dashboard::new()));
+        let mut writer = DumpWriter::append(&cfg.dump_path, cfg.keyframe_interval).unwrap();
+
+        create_db(cfg.aurora_db.path(), 86400.0, 800.0);
+        collect_snapshot(&cfg, &mut writer, &dashboard, &|_| {}).unwrap();
+
+        assert_eq!(dashboard.read().unwrap().games.len(), 1);
+        assert_eq!(DumpReader::open(&cfg.dump_path).unwrap().count(), 1);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 }
@@ -0,0 +1,221 @@
+use crate::calendar::SECONDS_PER_YEAR;
+use crate::dashboard::{Dashboard, DashboardField, GameDashboard, HistoricalValue};
+use serde::Serialize;
+
+/// smallest `step` of `Comparison::table`, in game years
+pub const MIN_STEP: f64 = 0.1;
+/// most rows returned by `Comparison::table`
+pub const MAX_ROWS: usize = 10_000;
+
+/// A metric of a game with `x` as elapsed game years since the start of the game
+#[derive(Debug, Clone, Serialize)]
+pub struct ComparedGame {
+    pub game_id: u32,
+    pub game_name: String,
+    pub start_year: u32,
+    /// empty when the game doesn't have the metric
+    pub values: Vec<HistoricalValue>,
+}
+
+/// elapsed game years and the value of every game at that time
+pub type Row = (f64, Vec<Option<f64>>);
+
+/// The same metric of several games aligned by elapsed years
+#[derive(Debug, Clone, Serialize)]
+pub struct Comparison {
+    pub metric: String,
+    pub games: Vec<ComparedGame>,
+}
+
+/// `metric` of `game`, searched in the game fields, the custom metrics and the empire totals
+fn find_metric(game: &GameDashboard, metric: &str) -> Option<DashboardField> {
+    let find = |fields: &[DashboardField]| fields.iter().find(|f| f.name == metric).cloned();
+    find(&game.fields)
+        .or_else(|| find(&game.custom))
+        .or_else(|| find(&game.totals()))
+}
+
+/// check `step` is a number of game years of at least `MIN_STEP`
+pub fn validate_step(step: f64) -> Result<(), String> {
+    if !(step >= MIN_STEP && step.is_finite()) {
+        return Err(format!(
+            "step must be at least {} years, got {}",
+            MIN_STEP, step
+        ));
+    }
+    Ok(())
+}
+
+/// decimals needed to print the years of the `table` rows of `step` without duplicates
+pub fn step_decimals(step: f64) -> usize {
+    (1..3)
+        .find(|decimals| {
+            let scaled = step * 10f64.powi(*decimals as i32);
+            (scaled - scaled.round()).abs() < 1e-6
+        })
+        .unwrap_or(3)
+}
+
+impl Comparison {
+    /// compare `metric` of `game_ids`, every game when empty, unknown games are ignored
+    pub fn new(dashboard: &Dashboard, metric: &str, game_ids: &[u32]) -> Comparison {
+        let games = dashboard
+            .games
+            .iter()
+            .filter(|game| game_ids.is_empty() || game_ids.contains(&game.game_id))
+            .map(|game| ComparedGame {
+                game_id: game.game_id,
+                game_name: game.game_name.clone(),
+                start_year: game.start_year,
+                values: find_metric(game, metric)
+                    .map(|field| {
+                        field
+                            .historical
+                            .iter()
+                            .map(|v| HistoricalValue {
+                                x: v.x / SECONDS_PER_YEAR,
+                                y: v.y,
+                            })
+                            .collect()
+                    })
+                    .unwrap_or_default(),
+            })
+            .collect();
+
+        Comparison {
+            metric: metric.to_string(),
+            games,
+        }
+    }
+
+    /// Rows of the value of every game every `step` years, from the start to the longest game.
+    ///
+    /// A game value is the last one known at that time, `None` before its first value or once a
+    /// full step has passed since its last one. Fails when `step` is invalid or would give more
+    /// than `MAX_ROWS` rows.
+    pub fn table(&self, step: f64) -> Result<Vec<Row>, String> {
+        validate_step(step)?;
+        let end = self
+            .games
+            .iter()
+            .filter_map(|game| game.values.last())
+            .map(|v| v.x)
+            .fold(0.0, f64::max);
+        let last_index = (end / step).floor() as usize + 1;
+        if last_index >= MAX_ROWS {
+            return Err(format!(
+                "step must be at least {:.1} years to compare {:.1} years in {} rows",
+                end / (MAX_ROWS - 2) as f64,
+                end,
+                MAX_ROWS
+            ));
+        }
+
+        let mut rows = vec![];
+        for index in 0..=last_index {
+            let years = index as f64 * step;
+            let values: Vec<Option<f64>> = self
+                .games
+                .iter()
+                .map(|game| {
+                    let last = game.values.last()?;
+                    if years - step >= last.x {
+                        return None;
+                    }
+                    game.values.iter().rev().find(|v| v.x <= years).map(|v| v.y)
+                })
+                .collect();
+            rows.push((years, values));
+        }
+
+        // the last row only exists to show the end of the longest game
+        if rows
+            .last()
+            .is_some_and(|(_, values)| values.iter().all(|v| v.is_none()))
+        {
+            rows.pop();
+        }
+        Ok(rows)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::aurora_db::AuroraData;
+    use crate::dashboard::test::game_data;
+
+    #[test]
+    fn comparison_should_align_games_by_elapsed_years() {
+        let mut dashboard = Dashboard::new();
+        let mut snapshots = vec![];
+        for (game_id, years, population) in [
+            (0, 0.5, 10.0),
+            (0, 1.5, 20.0),
+            (0, 2.5, 30.0),
+            (1, 0.0, 5.0),
+            (1, 1.0, 50.0),
+        ] {
+            let mut data = game_data(years * SECONDS_PER_YEAR);
+            data.game.game_id = game_id;
+            data.populations[0]
+                .values
+                .insert("population".to_string(), population);
+            snapshots.push(data);
+        }
+        dashboard.append(&AuroraData { games: snapshots });
+
+        let comparison = Comparison::new(&dashboard, "population", &[]);
+        assert_eq!(comparison.games.len(), 2);
+        assert_eq!(comparison.games[0].values[1].x, 1.5);
+        assert_eq!(comparison.games[1].values[1].y, 50.0);
+
+        let table = comparison.table(1.0).unwrap();
+        assert_eq!(
+            table,
+            vec![
+                (0.0, vec![None, Some(5.0)]),
+                (1.0, vec![Some(10.0), Some(50.0)]),
+                (2.0, vec![Some(20.0), None]),
+                (3.0, vec![Some(30.0), None]),
+            ]
+        );
+
+        assert_eq!(comparison.table(0.5).unwrap().len(), 6);
+        for step in [0.0, 0.05, -1.0, f64::NAN, f64::INFINITY] {
+            assert!(comparison.table(step).is_err(), "step {}", step);
+        }
+
+        let wealth = Comparison::new(&dashboard, "wealth", &[1]);
+        assert_eq!(wealth.games.len(), 1);
+        assert_eq!(wealth.games[0].values.len(), 2);
+        assert!(Comparison::new(&dashboard, "missing", &[])
+            .games
+            .iter()
+            .all(|game| game.values.is_empty()));
+    }
+
+    #[test]
+    fn comparison_should_bound_table_rows() {
+        let comparison = Comparison {
+            metric: "population".to_string(),
+            games: vec![ComparedGame {
+                game_id: 0,
+                game_name: "long".to_string(),
+                start_year: 2025,
+                values: vec![HistoricalValue { x: 5000.0, y: 1.0 }],
+            }],
+        };
+
+        assert!(comparison.table(0.1).is_err());
+        assert!(comparison.table(1.0).unwrap().len() <= MAX_ROWS);
+    }
+
+    #[test]
+    fn step_decimals_should_tell_rows_apart() {
+        assert_eq!(step_decimals(1.0), 1);
+        assert_eq!(step_decimals(0.5), 1);
+        assert_eq!(step_decimals(0.25), 2);
+        assert_eq!(step_decimals(1.0 / 3.0), 3);
+    }
+}
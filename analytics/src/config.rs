@@ -0,0 +1,314 @@
+use crate::alerts::AlertRule;
+use crate::anomalies::AnomalyCfg;
+use crate::archive::ArchiveCfg;
+use crate::aurora_db::AuroraDb;
+use crate::dashboard::{self, DerivedField};
+use crate::dump::DEFAULT_KEYFRAME_INTERVAL;
+use crate::field_map::FieldMap;
+use crate::hooks::HookCfg;
+use crate::metrics::MetricSet;
+use clap::Args;
+use rocket::figment::providers::{Env, Format, Serialized, Toml};
+use rocket::figment::Figment;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// default configuration file, read from the working directory when present
+pub const DEFAULT_CONFIG_PATH: &str = "aurora_analytics.toml";
+
+/// prefix of environment variables that override the configuration file,
+/// e.g. `AURORA_ANALYTICS_DUMP_PATH`
+pub const ENV_PREFIX: &str = "AURORA_ANALYTICS_";
+
+const DB_FILENAME: &str = "AuroraDB.db";
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Invalid(String),
+}
+
+/// Settings shared by the web server and the collector binaries.
+///
+/// Values are resolved from the defaults, then the configuration file, then environment
+/// variables with the `AURORA_ANALYTICS_` prefix and finally from command line arguments.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Config {
+    /// Aurora install folder, where `AuroraDB.db` is located
+    pub aurora_path: Option<String>,
+    pub dump_path: String,
+    pub dashboard_path: String,
+    /// snapshots between two full keyframes of the dump, the others only store changed values
+    pub keyframe_interval: usize,
+    /// game years of history kept at full detail by `compact`, older values are reduced to one
+    /// per year
+    pub retention_years: Option<u32>,
+    /// folder where a copy of `AuroraDB.db` is archived on every save, disabled when absent
+    pub archive_path: Option<String>,
+    /// archives kept, 0 for no limit
+    pub archive_max_count: usize,
+    /// total size of the archives in megabytes, 0 for no limit
+    pub archive_max_size_mb: u64,
+    /// TOML or JSON file with extra Aurora columns to track
+    pub field_map_path: Option<String>,
+    /// TOML or JSON file with SQL metrics evaluated on every snapshot
+    pub metrics_path: Option<String>,
+    /// fields computed from expressions over the other dashboard fields
+    pub derived: Vec<DerivedField>,
+    /// thresholds on dashboard fields checked after every snapshot
+    pub alerts: Vec<AlertRule>,
+    /// shell command run on every snapshot and alert, with the JSON event on stdin
+    pub hook_command: Option<String>,
+    /// `http://` URL on a loopback address receiving every snapshot and alert as a JSON POST
+    pub hook_url: Option<String>,
+    /// changes used as the recent trend by the anomaly detection
+    pub anomaly_window: usize,
+    /// deviation from the trend, in standard deviations, reported as an anomaly
+    pub anomaly_threshold: f64,
+    pub address: String,
+    pub port: u16,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            aurora_path: None,
+            dump_path: "data/aurora_dump.json".to_string(),
+            dashboard_path: "data/dashboard_data.json".to_string(),
+            keyframe_interval: DEFAULT_KEYFRAME_INTERVAL,
+            retention_years: None,
+            archive_path: None,
+            archive_max_count: 20,
+            archive_max_size_mb: 0,
+            field_map_path: None,
+            metrics_path: None,
+            derived: vec![],
+            alerts: vec![],
+            hook_command: None,
+            hook_url: None,
+            anomaly_window: AnomalyCfg::default().window,
+            anomaly_threshold: AnomalyCfg::default().threshold,
+            address: "127.0.0.1".to_string(),
+            port: 8000,
+        }
+    }
+}
+
+/// Values provided by the command line, they take precedence over any other source
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Args)]
+pub struct ConfigOverrides {
+    /// configuration file [default: aurora_analytics.toml]
+    #[serde(skip)]
+    #[arg(long = "config", global = true)]
+    pub config_path: Option<String>,
+    /// Aurora install folder, where AuroraDB.db is located
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[arg(long, global = true)]
+    pub aurora_path: Option<String>,
+    /// file where every snapshot of the Aurora database is appended
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[arg(long, global = true)]
+    pub dump_path: Option<String>,
+    /// file where the dashboard generated from the dump is stored
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[arg(long, global = true)]
+    pub dashboard_path: Option<String>,
+    /// web server bind address
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[arg(long, global = true)]
+    pub address: Option<String>,
+    /// web server port
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[arg(long, global = true)]
+    pub port: Option<u16>,
+}
+
+impl Config {
+    pub fn load(overrides: &ConfigOverrides) -> Result<Config, ConfigError> {
+        let config: Config = Config::figment(overrides)
+            .extract()
+            .map_err(|e| ConfigError::Invalid(format!("{}", e)))?;
+
+        for rule in &config.alerts {
+            rule.validate().map_err(ConfigError::Invalid)?;
+        }
+        dashboard::validate_derived(&config.derived, &config.field_map()?)
+            .map_err(ConfigError::Invalid)?;
+        config
+            .anomaly_cfg()
+            .validate()
+            .map_err(ConfigError::Invalid)?;
+        Ok(config)
+    }
+
+    pub fn figment(overrides: &ConfigOverrides) -> Figment {
+        let config_path = overrides
+            .config_path
+            .clone()
+            .or_else(|| std::env::var(format!("{}CONFIG", ENV_PREFIX)).ok())
+            .unwrap_or_else(|| DEFAULT_CONFIG_PATH.to_string());
+
+        Figment::from(Serialized::defaults(Config::default()))
+            .merge(Toml::file(config_path))
+            .merge(Env::prefixed(ENV_PREFIX).ignore(&["config"]))
+            .merge(Serialized::defaults(overrides))
+    }
+
+    /// extra columns to track, empty when no field map is configured
+    pub fn field_map(&self) -> Result<FieldMap, ConfigError> {
+        match &self.field_map_path {
+            Some(path) => {
+                FieldMap::load(path).map_err(|e| ConfigError::Invalid(format!("{:?}", e)))
+            }
+            None => Ok(FieldMap::default()),
+        }
+    }
+
+    /// user defined SQL metrics, empty when no metrics file is configured
+    pub fn metrics(&self) -> Result<MetricSet, ConfigError> {
+        match &self.metrics_path {
+            Some(path) => {
+                MetricSet::load(path).map_err(|e| ConfigError::Invalid(format!("{:?}", e)))
+            }
+            None => Ok(MetricSet::default()),
+        }
+    }
+
+    /// Aurora database of the install folder, with the field map and metrics
+    pub fn aurora_db(&self) -> Result<AuroraDb, ConfigError> {
+        Ok(self.aurora_db_template()?.at(Path::new(&self.db_path()?)))
+    }
+
+    /// field map and metrics without a database, `AuroraDb::at` gives the path of each database
+    /// read, e.g. the backups of a backfill
+    pub fn aurora_db_template(&self) -> Result<AuroraDb, ConfigError> {
+        Ok(AuroraDb::default()
+            .with_field_map(self.field_map()?)
+            .with_metrics(self.metrics()?))
+    }
+
+    /// archive settings, `None` when archiving is disabled
+    pub fn archive_cfg(&self) -> Option<ArchiveCfg> {
+        self.archive_path.as_ref().map(|dir| ArchiveCfg {
+            dir: dir.clone(),
+            max_count: Some(self.archive_max_count).filter(|max| *max > 0),
+            max_size: Some(self.archive_max_size_mb * 1024 * 1024).filter(|max| *max > 0),
+        })
+    }
+
+    /// notifications of the collector
+    pub fn hooks(&self) -> HookCfg {
+        HookCfg {
+            command: self.hook_command.clone(),
+            url: self.hook_url.clone(),
+        }
+    }
+
+    pub fn anomaly_cfg(&self) -> AnomalyCfg {
+        AnomalyCfg {
+            window: self.anomaly_window,
+            threshold: self.anomaly_threshold,
+        }
+    }
+
+    /// path of the Aurora database inside the install folder
+    pub fn db_path(&self) -> Result<String, ConfigError> {
+        let aurora_path = self.aurora_path.as_ref().ok_or_else(|| {
+            ConfigError::Invalid("path to aurora 4x must be provided".to_string())
+        })?;
+
+        let path = PathBuf::from(aurora_path).join(DB_FILENAME);
+        Ok(path.to_string_lossy().to_string())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use clap::Parser;
+
+    #[derive(Parser)]
+    struct TestCli {
+        #[command(flatten)]
+        overrides: ConfigOverrides,
+    }
+
+    fn parse(values: &[&str]) -> Result<ConfigOverrides, clap::Error> {
+        let args = std::iter::once("test").chain(values.iter().cloned());
+        TestCli::try_parse_from(args).map(|cli| cli.overrides)
+    }
+
+    #[test]
+    fn config_should_use_defaults() {
+        let overrides = ConfigOverrides {
+            config_path: Some("not_existent.toml".to_string()),
+            ..ConfigOverrides::default()
+        };
+
+        let config = Config::load(&overrides).unwrap();
+        assert_eq!(config.dump_path, "data/aurora_dump.json");
+        assert_eq!(config.dashboard_path, "data/dashboard_data.json");
+        assert_eq!(config.port, 8000);
+        assert!(config.db_path().is_err());
+    }
+
+    #[test]
+    fn config_should_apply_command_line_overrides() {
+        let mut overrides = parse(&[
+            "--aurora-path",
+            "/games/aurora",
+            "--dump-path",
+            "/tmp/dump.json",
+            "--port",
+            "9000",
+        ])
+        .unwrap();
+        overrides.config_path = Some("not_existent.toml".to_string());
+
+        let config = Config::load(&overrides).unwrap();
+        assert_eq!(config.aurora_path, Some("/games/aurora".to_string()));
+        assert_eq!(config.db_path().unwrap(), "/games/aurora/AuroraDB.db");
+        assert_eq!(config.dump_path, "/tmp/dump.json");
+        assert_eq!(config.dashboard_path, "data/dashboard_data.json");
+        assert_eq!(config.port, 9000);
+    }
+
+    #[test]
+    fn config_should_reject_invalid_anomaly_settings() {
+        let path = std::env::temp_dir().join(format!(
+            "aurora_analytics_{}_anomaly.toml",
+            std::process::id()
+        ));
+        let overrides = ConfigOverrides {
+            config_path: Some(path.to_str().unwrap().to_string()),
+            ..ConfigOverrides::default()
+        };
+
+        for settings in [
+            "anomaly_window = 2",
+            "anomaly_threshold = 0.0",
+            "anomaly_threshold = -1.0",
+            "anomaly_threshold = nan",
+            "anomaly_threshold = inf",
+        ] {
+            std::fs::write(&path, settings).unwrap();
+            match Config::load(&overrides) {
+                Err(ConfigError::Invalid(msg)) => assert!(msg.contains("anomaly"), "{}", msg),
+                Ok(_) => panic!("{} should be rejected", settings),
+            }
+        }
+
+        std::fs::write(&path, "anomaly_window = 3\nanomaly_threshold = 2.5").unwrap();
+        let config = Config::load(&overrides).unwrap();
+        assert_eq!(config.anomaly_window, 3);
+        assert_eq!(config.anomaly_threshold, 2.5);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn config_should_fail_on_invalid_arguments() {
+        assert!(parse(&["--port", "abc"]).is_err());
+        assert!(parse(&["--unknown"]).is_err());
+        assert!(parse(&["--dump-path"]).is_err());
+    }
+}
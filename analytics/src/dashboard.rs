@@ -1,10 +1,16 @@
+use crate::alerts::{Alert, AlertRule};
 use crate::aurora_db::AuroraData;
+use crate::calendar::GameDate;
+use crate::dump::DumpReader;
+use crate::expr::Expr;
+use crate::field_map::{FieldMap, Scope};
 use serde::{Deserialize, Serialize};
-use std::fs::File;
-use std::io::BufRead;
+use std::sync::{Arc, RwLock};
 
 #[derive(Debug)]
-pub enum DbError {}
+pub enum DbError {
+    Generic(String),
+}
 
 #[derive(Debug)]
 pub struct DashboardDb {}
@@ -19,6 +25,63 @@ pub struct HistoricalValue {
 pub struct DashboardField {
     pub name: String,
     pub historical: Vec<HistoricalValue>,
+    /// computed by a `DerivedField`, not summed into the totals
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub derived: bool,
+}
+
+/// Selection of historical values by game time with a optional limit of points
+#[derive(Debug, Clone, Default)]
+pub struct SeriesFilter {
+    pub from: Option<f64>,
+    pub to: Option<f64>,
+    /// when there are more values than this, they are downsampled by averaging consecutive values
+    pub max_points: Option<usize>,
+}
+
+impl SeriesFilter {
+    pub fn apply(&self, historical: &[HistoricalValue]) -> Vec<HistoricalValue> {
+        let values: Vec<HistoricalValue> = historical
+            .iter()
+            .filter(|v| self.from.map(|from| v.x >= from).unwrap_or(true))
+            .filter(|v| self.to.map(|to| v.x <= to).unwrap_or(true))
+            .cloned()
+            .collect();
+
+        match self.max_points {
+            Some(max_points) => downsample(&values, max_points),
+            None => values,
+        }
+    }
+}
+
+/// Reduce `values` to at most `max_points` by replacing each group of consecutive values by its
+/// average. The first and last values are always kept so the chart still cover the full range.
+pub fn downsample(values: &[HistoricalValue], max_points: usize) -> Vec<HistoricalValue> {
+    if values.len() <= max_points || max_points == 0 {
+        return values.to_vec();
+    }
+
+    if max_points <= 2 {
+        let mut result = vec![values[0].clone(), values[values.len() - 1].clone()];
+        result.truncate(max_points);
+        return result;
+    }
+
+    let inner = &values[1..values.len() - 1];
+    let buckets = max_points - 2;
+    let bucket_size = inner.len().div_ceil(buckets);
+
+    let mut result = vec![values[0].clone()];
+    for chunk in inner.chunks(bucket_size) {
+        let len = chunk.len() as f64;
+        result.push(HistoricalValue {
+            x: chunk.iter().map(|v| v.x).sum::<f64>() / len,
+            y: chunk.iter().map(|v| v.y).sum::<f64>() / len,
+        });
+    }
+    result.push(values[values.len() - 1].clone());
+    result
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -26,6 +89,10 @@ pub struct DashboardPopulation {
     pub population_id: u32,
     pub population_name: String,
     pub fields: Vec<DashboardField>,
+    /// mineral deposits of the body, `<mineral>_amount` and `<mineral>_accessibility`, optional
+    /// as old dashboards don't have this field
+    #[serde(default)]
+    pub deposits: Vec<DashboardField>,
 }
 
 impl DashboardPopulation {
@@ -35,17 +102,157 @@ impl DashboardPopulation {
             .find(|f| f.name.as_str() == name)
             .unwrap()
     }
+
+    /// true if any field got a new value at `game_time` compared with its previous point
+    pub fn has_changed_at(&self, game_time: f64) -> bool {
+        self.fields.iter().any(|field| {
+            let index = field.historical.iter().position(|v| v.x == game_time);
+
+            match index {
+                None => false,
+                Some(0) => true,
+                Some(index) => field.historical[index - 1].y != field.historical[index].y,
+            }
+        })
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GameDashboard {
     pub game_id: u32,
     pub game_name: String,
+    /// required to compute dates, dashboards written without it must be rebuilt from the dump
+    pub start_year: u32,
     pub fields: Vec<DashboardField>,
     pub populations: Vec<DashboardPopulation>,
+    /// user defined metrics, optional as old dashboards don't have this field
+    #[serde(default)]
+    pub custom: Vec<DashboardField>,
+    /// alerts raised by the alert rules, optional as old dashboards don't have this field
+    #[serde(default)]
+    pub alerts: Vec<Alert>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+impl GameDashboard {
+    /// convert a historical `x` value into the game calendar
+    pub fn date(&self, game_time: f64) -> GameDate {
+        GameDate::from_game_time(self.start_year, game_time)
+    }
+
+    /// empire totals, each population field summed over all populations at every game time,
+    /// derived fields are left out as a sum of ratios or averages is meaningless
+    pub fn totals(&self) -> Vec<DashboardField> {
+        let mut totals: Vec<DashboardField> = vec![];
+
+        for pop in &self.populations {
+            for field in pop.fields.iter().filter(|f| !f.derived) {
+                for value in &field.historical {
+                    Dashboard::add_to_field(&mut totals, value.x, &field.name, value.y);
+                }
+            }
+        }
+
+        totals
+    }
+
+    /// populations with new or changed values at `game_time`
+    pub fn changed_populations(&self, game_time: f64) -> Vec<u32> {
+        self.populations
+            .iter()
+            .filter(|pop| pop.has_changed_at(game_time))
+            .map(|pop| pop.population_id)
+            .collect()
+    }
+}
+
+/// A field computed from the other fields of its game or population at each game time.
+///
+/// Population expressions use the population fields, game expressions use the game fields, the
+/// custom metrics and the empire totals. Times where a value is missing are skipped. The name
+/// can't be the one of a field read from the database, see `validate_derived`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DerivedField {
+    pub name: String,
+    pub expr: Expr,
+    #[serde(default)]
+    pub scope: Scope,
+}
+
+impl DerivedField {
+    /// compute the field from `sources`, any previously derived values are replaced
+    fn apply(&self, fields: &mut Vec<DashboardField>, sources: &[&[DashboardField]]) {
+        fields.retain(|f| !f.derived || f.name != self.name);
+
+        let find = |name: &str| {
+            sources
+                .iter()
+                .flat_map(|fields| fields.iter())
+                .find(|f| f.name == name)
+        };
+        let used: Vec<&DashboardField> = self.expr.names().into_iter().filter_map(find).collect();
+
+        let mut times: Vec<f64> = used
+            .iter()
+            .flat_map(|f| f.historical.iter().map(|v| v.x))
+            .collect();
+        times.sort_unstable_by(|a, b| a.total_cmp(b));
+        times.dedup();
+
+        let historical: Vec<HistoricalValue> = times
+            .into_iter()
+            .filter_map(|x| {
+                let lookup = |name: &str| {
+                    let field = find(name)?;
+                    let index = field
+                        .historical
+                        .binary_search_by(|v| v.x.total_cmp(&x))
+                        .ok()?;
+                    Some(field.historical[index].y)
+                };
+                self.expr.eval(&lookup).map(|y| HistoricalValue { x, y })
+            })
+            .collect();
+
+        if !historical.is_empty() {
+            fields.push(DashboardField {
+                name: self.name.clone(),
+                historical,
+                derived: true,
+            });
+        }
+    }
+}
+
+/// Check the names of the derived fields, they must be unique by scope and differ from the fields
+/// read with `field_map` and the built-in fields, which would otherwise be replaced.
+pub fn validate_derived(derived: &[DerivedField], field_map: &FieldMap) -> Result<(), String> {
+    for (index, field) in derived.iter().enumerate() {
+        let read = FieldMap::builtin()
+            .names(field.scope)
+            .into_iter()
+            .chain(field_map.names(field.scope))
+            .any(|name| name == field.name);
+        if read {
+            return Err(format!(
+                "derived field {} has the name of a field read from the database",
+                field.name
+            ));
+        }
+
+        if derived[..index]
+            .iter()
+            .any(|other| other.scope == field.scope && other.name == field.name)
+        {
+            return Err(format!("derived field {} is defined twice", field.name));
+        }
+    }
+    Ok(())
+}
+
+/// dashboard shared between the collector thread and the web server
+pub type SharedDashboard = Arc<RwLock<Dashboard>>;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Dashboard {
     pub games: Vec<GameDashboard>,
 }
@@ -70,8 +277,11 @@ impl Dashboard {
                     self.games.push(GameDashboard {
                         game_id: agame.game.game_id,
                         game_name: agame.game.game_name.clone(),
+                        start_year: agame.game.start_year,
                         fields: vec![],
                         populations: vec![],
+                        custom: vec![],
+                        alerts: vec![],
                     });
 
                     self.games.last_mut().unwrap()
@@ -80,6 +290,16 @@ impl Dashboard {
                 Some(game_data) => game_data,
             };
 
+            game_data.start_year = agame.game.start_year;
+
+            for (name, value) in &agame.values {
+                Dashboard::append_field(&mut game_data.fields, date, name, *value);
+            }
+
+            for (name, value) in &agame.custom {
+                Dashboard::append_field(&mut game_data.custom, date, name, *value);
+            }
+
             for ap in &agame.populations {
                 let pop: &mut DashboardPopulation = match game_data
                     .populations
@@ -91,6 +311,7 @@ impl Dashboard {
                             population_id: ap.population_id,
                             population_name: ap.pop_name.clone(),
                             fields: vec![],
+                            deposits: vec![],
                         });
 
                         game_data.populations.last_mut().unwrap()
@@ -99,85 +320,161 @@ impl Dashboard {
                     Some(pop) => pop,
                 };
 
-                macro_rules! append_field {
-                    ($f:tt) => {
-                        Dashboard::append_field(pop, date, std::stringify!($f), ap.$f);
-                    };
-                    ($f:tt, option) => {
-                        Dashboard::append_field(
-                            pop,
-                            date,
-                            std::stringify!($f),
-                            ap.$f.unwrap_or(0.0),
-                        );
-                    };
+                for (name, value) in &ap.values {
+                    Dashboard::append_field(&mut pop.fields, date, name, *value);
                 }
 
-                append_field!(fuel_stockpile);
-                append_field!(maintenance_stockpile);
-                append_field!(population);
-                append_field!(duranium);
-                append_field!(neutronium);
-                append_field!(corbomite);
-                append_field!(tritanium);
-                append_field!(boronide);
-                append_field!(mercassium);
-                append_field!(vendarite);
-                append_field!(sorium);
-                append_field!(uridium, option);
-                append_field!(corundium);
-                append_field!(gallicite);
+                for deposit in ap.minerals.iter().flatten() {
+                    let mineral = deposit.mineral();
+                    let amount = format!("{}_amount", mineral);
+                    Dashboard::append_field(&mut pop.deposits, date, &amount, deposit.amount);
+                    let accessibility = format!("{}_accessibility", mineral);
+                    Dashboard::append_field(&mut pop.deposits, date, &accessibility, deposit.acc);
+                }
             }
         }
     }
 
-    fn append_field(pop: &mut DashboardPopulation, date: f64, fieldname: &str, value: f64) {
-        let f: &mut DashboardField =
-            match pop.fields.iter_mut().find(|i| i.name.as_str() == fieldname) {
-                None => {
-                    pop.fields.push(DashboardField {
-                        name: fieldname.to_string(),
-                        historical: vec![],
-                    });
-
-                    pop.fields.last_mut().unwrap()
+    /// (re)compute the derived fields of every game and population, in order so a derived field
+    /// can use the previous ones
+    pub fn derive(&mut self, derived: &[DerivedField]) {
+        for game in self.games.iter_mut() {
+            for field in derived.iter().filter(|f| f.scope == Scope::Population) {
+                for pop in game.populations.iter_mut() {
+                    let sources = pop.fields.clone();
+                    field.apply(&mut pop.fields, &[&sources]);
                 }
+            }
 
-                Some(f) => f,
-            };
+            for field in derived.iter().filter(|f| f.scope == Scope::Game) {
+                let totals = game.totals();
+                let custom = game.custom.clone();
+                let sources = game.fields.clone();
+                field.apply(&mut game.fields, &[&sources, &custom, &totals]);
+            }
+        }
+    }
+
+    /// (re)evaluate the alert rules over the whole history of every game
+    pub fn evaluate_alerts(&mut self, rules: &[AlertRule]) {
+        for game in self.games.iter_mut() {
+            game.alerts = rules.iter().flat_map(|rule| rule.check(game)).collect();
+            game.alerts
+                .sort_by(|a, b| a.game_time.total_cmp(&b.game_time));
+        }
+    }
+
+    /// add `value` into the field value at `date`, creating it when missing
+    fn add_to_field(fields: &mut Vec<DashboardField>, date: f64, fieldname: &str, value: f64) {
+        let existing = fields
+            .iter_mut()
+            .find(|i| i.name.as_str() == fieldname)
+            .and_then(|f| f.historical.iter_mut().find(|v| v.x == date));
+
+        match existing {
+            Some(existing) => existing.y += value,
+            None => Dashboard::append_field(fields, date, fieldname, value),
+        }
+    }
+
+    fn append_field(fields: &mut Vec<DashboardField>, date: f64, fieldname: &str, value: f64) {
+        let f: &mut DashboardField = match fields.iter_mut().find(|i| i.name.as_str() == fieldname)
+        {
+            None => {
+                fields.push(DashboardField {
+                    name: fieldname.to_string(),
+                    historical: vec![],
+                    derived: false,
+                });
+
+                fields.last_mut().unwrap()
+            }
+
+            Some(f) => f,
+        };
 
         // horrible sorting after insert, but will be noticed?
         f.historical.push(HistoricalValue { x: date, y: value });
-        f.historical
-            .sort_unstable_by(|a, b| a.x.partial_cmp(&b.x).unwrap());
+        f.historical.sort_unstable_by(|a, b| a.x.total_cmp(&b.x));
     }
 }
 
 impl DashboardDb {
     pub fn load_aurora_dump(path: &str) -> Result<Dashboard, DbError> {
-        // TODO: add error handling
         let mut db_data = Dashboard::new();
-        let file = File::open(&path).unwrap();
-        for line in std::io::BufReader::new(file).lines() {
-            let data: AuroraData = serde_json::from_str(&line.unwrap()).unwrap();
+        let reader = DumpReader::open(path).map_err(|e| DbError::Generic(format!("{:?}", e)))?;
+        for data in reader {
+            let data = data.map_err(|e| DbError::Generic(format!("{:?}", e)))?;
             db_data.append(&data);
         }
         Ok(db_data)
     }
 
+    pub fn load(path: &str) -> Result<Dashboard, DbError> {
+        let body = std::fs::read_to_string(path)
+            .map_err(|e| DbError::Generic(format!("fail to open {}: {}", path, e)))?;
+        let value: serde_json::Value = serde_json::from_str(&body)
+            .map_err(|e| DbError::Generic(format!("fail to parse {}: {}", path, e)))?;
+
+        // dashboards written before dates were supported can't be read without guessing them
+        let games = value["games"].as_array().map(Vec::as_slice).unwrap_or(&[]);
+        if games.iter().any(|game| game.get("start_year").is_none()) {
+            return Err(DbError::Generic(format!(
+                "{} was written by an older version without game start years, run \
+                 `aurora-analytics rebuild-dashboard` to rebuild it from the dump",
+                path
+            )));
+        }
+
+        serde_json::from_value(value)
+            .map_err(|e| DbError::Generic(format!("fail to parse {}: {}", path, e)))
+    }
+
     pub fn save(dashboard: &Dashboard, path: &str) -> Result<(), DbError> {
-        // TODO: add error handling
-        let json = serde_json::to_string_pretty(dashboard).unwrap();
-        std::fs::write(path, json).unwrap();
+        let json = serde_json::to_string_pretty(dashboard)
+            .map_err(|e| DbError::Generic(format!("fail to serialize dashboard: {}", e)))?;
+        // write into a temporary file and rename so readers never see a half written file
+        let tmp_path = format!("{}.tmp", path);
+        std::fs::write(&tmp_path, json)
+            .map_err(|e| DbError::Generic(format!("fail to write {}: {}", tmp_path, e)))?;
+        std::fs::rename(&tmp_path, path)
+            .map_err(|e| DbError::Generic(format!("fail to write {}: {}", path, e)))?;
         println!("dashboard updated at {}", path);
         Ok(())
     }
 }
 
 #[cfg(test)]
-mod test {
+pub(crate) mod test {
     use super::*;
-    use crate::aurora_db::{AuroraGameData, FCTGame, FCTMineralDeposit, FCTPopulation, FCTRace};
+    use crate::aurora_db::{AuroraGameData, FCTGame, FCTMineralDeposit, FCTPopulation};
+    use indexmap::IndexMap;
+
+    fn values(values: &[(&str, f64)]) -> IndexMap<String, f64> {
+        values
+            .iter()
+            .map(|(name, value)| (name.to_string(), *value))
+            .collect()
+    }
+
+    /// built-in population fields of the test populations, without uridium
+    fn population_values() -> IndexMap<String, f64> {
+        values(&[
+            ("fuel_stockpile", 1.0),
+            ("maintenance_stockpile", 2.0),
+            ("population", 3.0),
+            ("duranium", 4.0),
+            ("neutronium", 5.0),
+            ("corbomite", 6.0),
+            ("tritanium", 7.0),
+            ("boronide", 8.0),
+            ("mercassium", 9.0),
+            ("vendarite", 10.0),
+            ("sorium", 11.0),
+            ("corundium", 11.0),
+            ("gallicite", 12.0),
+        ])
+    }
 
     fn game_data_old(time: f64) -> AuroraGameData {
         AuroraGameData {
@@ -194,27 +491,15 @@ mod test {
                 system_id: None,
                 system_body_id: None,
                 pop_name: "Pop 1".to_string(),
-                fuel_stockpile: 1.0,
-                maintenance_stockpile: 2.0,
-                population: 3.0,
-                duranium: 4.0,
-                neutronium: 5.0,
-                corbomite: 6.0,
-                tritanium: 7.0,
-                boronide: 8.0,
-                mercassium: 9.0,
-                vendarite: 10.0,
-                sorium: 11.0,
-                corundium: 11.0,
-                uridium: None,
-                gallicite: 12.0,
                 minerals: None,
+                values: population_values(),
             }],
-            race: None,
+            values: Default::default(),
+            custom: Default::default(),
         }
     }
 
-    fn game_data(time: f64) -> AuroraGameData {
+    pub fn game_data(time: f64) -> AuroraGameData {
         AuroraGameData {
             game: FCTGame {
                 game_id: 0,
@@ -229,20 +514,6 @@ mod test {
                 system_id: None,
                 system_body_id: None,
                 pop_name: "Pop 1".to_string(),
-                fuel_stockpile: 1.0,
-                maintenance_stockpile: 2.0,
-                population: 3.0,
-                duranium: 4.0,
-                neutronium: 5.0,
-                corbomite: 6.0,
-                tritanium: 7.0,
-                boronide: 8.0,
-                mercassium: 9.0,
-                vendarite: 10.0,
-                sorium: 11.0,
-                corundium: 11.0,
-                uridium: None,
-                gallicite: 12.0,
                 minerals: Some(vec![
                     FCTMineralDeposit {
                         material_id: 1,
@@ -255,12 +526,10 @@ mod test {
                         acc: 0.8,
                     },
                 ]),
+                values: population_values(),
             }],
-            race: Some(FCTRace {
-                race_id: 4,
-                wealth: 40.0,
-                annual_wealth: 10.0,
-            }),
+            values: values(&[("wealth", 40.0), ("annual_wealth", 10.0)]),
+            custom: Default::default(),
         }
     }
 
@@ -288,6 +557,199 @@ mod test {
         );
     }
 
+    fn series(size: usize) -> Vec<HistoricalValue> {
+        (0..size)
+            .map(|i| HistoricalValue {
+                x: i as f64,
+                y: i as f64 * 10.0,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn series_filter_should_select_range() {
+        let filter = SeriesFilter {
+            from: Some(2.0),
+            to: Some(4.0),
+            max_points: None,
+        };
+
+        let values = filter.apply(&series(10));
+        let xs: Vec<f64> = values.iter().map(|v| v.x).collect();
+        assert_eq!(xs, vec![2.0, 3.0, 4.0]);
+    }
+
+    #[test]
+    fn series_filter_should_downsample_keeping_edges() {
+        let filter = SeriesFilter {
+            max_points: Some(5),
+            ..SeriesFilter::default()
+        };
+
+        let values = filter.apply(&series(101));
+        assert!(values.len() <= 5);
+        assert_eq!(values[0].x, 0.0);
+        assert_eq!(values[values.len() - 1].x, 100.0);
+        // averages keep the linear relation
+        for v in &values {
+            assert!((v.y - v.x * 10.0).abs() < 0.001);
+        }
+
+        assert_eq!(downsample(&series(3), 5).len(), 3);
+        assert_eq!(downsample(&series(10), 1).len(), 1);
+    }
+
+    #[test]
+    fn dbdata_should_sum_empire_totals() {
+        let mut second_pop = game_data(2.0);
+        second_pop.populations[0].population_id = 2;
+        second_pop.populations[0]
+            .values
+            .insert("population".to_string(), 10.0);
+
+        let mut db_data = Dashboard::new();
+        db_data.append(&AuroraData {
+            games: vec![game_data(1.0), game_data(2.0), second_pop],
+        });
+
+        let totals = db_data.games[0].totals();
+        let population = totals.iter().find(|f| f.name == "population").unwrap();
+        assert_eq!(population.historical.len(), 2);
+        assert_eq!(population.historical[0].y, 3.0);
+        assert_eq!(population.historical[1].y, 13.0);
+    }
+
+    #[test]
+    fn dbdata_should_list_changed_populations() {
+        let mut db_data = Dashboard::new();
+        db_data.append(&AuroraData {
+            games: vec![game_data(1.0)],
+        });
+        assert_eq!(db_data.games[0].changed_populations(1.0), vec![1]);
+
+        db_data.append(&AuroraData {
+            games: vec![game_data(2.0)],
+        });
+        assert!(db_data.games[0].changed_populations(2.0).is_empty());
+
+        let mut changed = game_data(3.0);
+        changed.populations[0]
+            .values
+            .insert("population".to_string(), 4.0);
+        db_data.append(&AuroraData {
+            games: vec![changed],
+        });
+        assert_eq!(db_data.games[0].changed_populations(3.0), vec![1]);
+    }
+
+    #[test]
+    fn dbdata_should_compute_derived_fields() {
+        let mut second_pop = game_data(2.0);
+        second_pop.populations[0].population_id = 2;
+        second_pop.populations[0]
+            .values
+            .insert("fuel_stockpile".to_string(), 0.0);
+
+        let mut db_data = Dashboard::new();
+        db_data.append(&AuroraData {
+            games: vec![game_data(1.0), game_data(2.0), second_pop],
+        });
+
+        let derived: Vec<DerivedField> = serde_json::from_str(
+            r#"[
+                {"name": "metals", "expr": "duranium + tritanium"},
+                {"name": "per_fuel", "expr": "metals / fuel_stockpile"},
+                {"name": "wealth_per_pop", "expr": "wealth / population", "scope": "game"},
+                {"name": "broken", "expr": "population * missing"}
+            ]"#,
+        )
+        .unwrap();
+        db_data.derive(&derived);
+        // computing again replaces the previous values
+        db_data.derive(&derived);
+
+        let game = &db_data.games[0];
+        let metals = game.populations[0].get_field("metals");
+        assert_eq!(metals.historical.len(), 2);
+        assert_eq!(metals.historical[1].y, 11.0);
+        assert_eq!(
+            game.populations[0].get_field("per_fuel").historical[0].y,
+            11.0
+        );
+        // division by zero is skipped
+        assert!(game.populations[1]
+            .fields
+            .iter()
+            .all(|f| f.name != "per_fuel"));
+        assert!(game.populations[0]
+            .fields
+            .iter()
+            .all(|f| f.name != "broken"));
+
+        let wealth = game.fields.iter().find(|f| f.name == "wealth_per_pop");
+        let values: Vec<f64> = wealth.unwrap().historical.iter().map(|v| v.y).collect();
+        assert_eq!(values, vec![40.0 / 3.0, 40.0 / 6.0]);
+
+        // derived population fields are not summed into the totals
+        let totals = game.totals();
+        assert!(totals.iter().all(|f| f.name != "metals"));
+        assert!(totals.iter().any(|f| f.name == "duranium"));
+    }
+
+    #[test]
+    fn derived_fields_should_not_replace_read_fields() {
+        let derived = |json: &str| -> Vec<DerivedField> { serde_json::from_str(json).unwrap() };
+        let field_map: FieldMap = serde_json::from_str(
+            r#"{"tables": [{"table": "FCT_Population", "scope": "population", "key": "PopulationID",
+                "fields": {"Fuel": "fuel"}}]}"#,
+        )
+        .unwrap();
+
+        let valid = derived(
+            r#"[{"name": "metals", "expr": "duranium + tritanium"},
+                {"name": "metals", "expr": "wealth", "scope": "game"}]"#,
+        );
+        assert!(validate_derived(&valid, &field_map).is_ok());
+
+        for invalid in &[
+            r#"[{"name": "population", "expr": "population * 2"}]"#,
+            r#"[{"name": "wealth", "expr": "wealth * 2", "scope": "game"}]"#,
+            r#"[{"name": "fuel", "expr": "fuel * 2"}]"#,
+            r#"[{"name": "metals", "expr": "duranium"}, {"name": "metals", "expr": "tritanium"}]"#,
+        ] {
+            assert!(validate_derived(&derived(invalid), &field_map).is_err());
+        }
+    }
+
+    #[test]
+    fn dashboard_db_should_reject_dashboards_without_start_year() {
+        let path = std::env::temp_dir().join(format!(
+            "aurora_analytics_{}_old_dashboard.json",
+            std::process::id()
+        ));
+        let path = path.to_string_lossy().to_string();
+
+        let mut dashboard = Dashboard::new();
+        dashboard.append(&AuroraData {
+            games: vec![game_data(1.0)],
+        });
+        DashboardDb::save(&dashboard, &path).unwrap();
+        assert_eq!(DashboardDb::load(&path).unwrap().games[0].start_year, 2);
+
+        let mut old = serde_json::to_value(&dashboard).unwrap();
+        old["games"][0]
+            .as_object_mut()
+            .unwrap()
+            .remove("start_year");
+        std::fs::write(&path, old.to_string()).unwrap();
+        match DashboardDb::load(&path) {
+            Err(DbError::Generic(msg)) => assert!(msg.contains("rebuild-dashboard"), "{}", msg),
+            Ok(_) => panic!("old dashboard should be rejected"),
+        }
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
     #[test]
     fn dbdata_should_keep_historical_data_sorted_by_time() {
         let mut db_data = Dashboard::new();
@@ -315,15 +777,18 @@ mod test {
         let historical = &db_data.games[0].populations[0].fields[0].historical;
         assert!(
             (historical[0].x - 1.0).abs() < 0.1,
-            format!("fail at {}", historical[0].x)
+            "fail at {}",
+            historical[0].x
         );
         assert!(
             (historical[1].x - 3.0).abs() < 0.1,
-            format!("fail at {}", historical[1].x)
+            "fail at {}",
+            historical[1].x
         );
         assert!(
             (historical[2].x - 5.0).abs() < 0.1,
-            format!("fail at {}", historical[2].x)
+            "fail at {}",
+            historical[2].x
         );
     }
 }
@@ -0,0 +1,659 @@
+use crate::aurora_db::AuroraData;
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Lines, Read, Write};
+use std::time::SystemTime;
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// snapshots written between two keyframes when no interval is configured
+pub const DEFAULT_KEYFRAME_INTERVAL: usize = 24;
+
+#[derive(Debug)]
+pub enum DumpError {
+    Generic(String),
+}
+
+/// How snapshots are stored in a dump file, both are append only with one record per line
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DumpFormat {
+    JsonLines,
+    /// gzip compressed JSON lines, every append adds a new gzip member to the file
+    GzipJsonLines,
+}
+
+impl DumpFormat {
+    /// format used when writing, files ending with `.gz` are compressed
+    pub fn from_path(path: &str) -> Self {
+        if path.ends_with(".gz") {
+            DumpFormat::GzipJsonLines
+        } else {
+            DumpFormat::JsonLines
+        }
+    }
+
+    /// Format of the dump at `path` from its first bytes like `DumpReader`, so appends keep the
+    /// format of an existing dump whatever its extension. Falls back to `from_path` when the file
+    /// is missing or empty.
+    pub fn detect(path: &str) -> Self {
+        let mut magic = [0u8; 2];
+        match File::open(path).and_then(|mut file| file.read(&mut magic)) {
+            Ok(0) | Err(_) => DumpFormat::from_path(path),
+            Ok(_) if magic == GZIP_MAGIC => DumpFormat::GzipJsonLines,
+            Ok(_) => DumpFormat::JsonLines,
+        }
+    }
+}
+
+/// A line of the dump.
+///
+/// Keyframes hold the full snapshot while deltas only hold the values changed since the
+/// previous snapshot. Dumps written before deltas existed have a plain `AuroraData` per line,
+/// those lines are read as keyframes.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum DumpRecord {
+    Keyframe(Value),
+    Delta(ValueDelta),
+}
+
+/// Field level difference between two JSON documents
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum ValueDelta {
+    /// value replaced
+    Set(Value),
+    /// key not present anymore
+    Remove,
+    /// changed keys of an object
+    Object(IndexMap<String, ValueDelta>),
+    /// new length and changed items of an array
+    Array {
+        len: usize,
+        items: BTreeMap<usize, ValueDelta>,
+    },
+}
+
+impl ValueDelta {
+    /// changes required to turn `old` into `new`, `None` when both are equal
+    fn diff(old: &Value, new: &Value) -> Option<ValueDelta> {
+        match (old, new) {
+            (Value::Object(old), Value::Object(new)) => {
+                let mut changes = IndexMap::new();
+                for (key, value) in new {
+                    let change = match old.get(key) {
+                        Some(old_value) => ValueDelta::diff(old_value, value),
+                        None => Some(ValueDelta::Set(value.clone())),
+                    };
+                    if let Some(change) = change {
+                        changes.insert(key.clone(), change);
+                    }
+                }
+                for key in old.keys().filter(|key| !new.contains_key(*key)) {
+                    changes.insert(key.clone(), ValueDelta::Remove);
+                }
+
+                if changes.is_empty() {
+                    None
+                } else {
+                    Some(ValueDelta::Object(changes))
+                }
+            }
+            (Value::Array(old), Value::Array(new)) => {
+                let mut items = BTreeMap::new();
+                for (i, value) in new.iter().enumerate() {
+                    let change = match old.get(i) {
+                        Some(old_value) => ValueDelta::diff(old_value, value),
+                        None => Some(ValueDelta::Set(value.clone())),
+                    };
+                    if let Some(change) = change {
+                        items.insert(i, change);
+                    }
+                }
+
+                if items.is_empty() && old.len() == new.len() {
+                    None
+                } else {
+                    Some(ValueDelta::Array {
+                        len: new.len(),
+                        items,
+                    })
+                }
+            }
+            (old, new) if old == new => None,
+            (_, new) => Some(ValueDelta::Set(new.clone())),
+        }
+    }
+
+    fn apply(self, target: &mut Value) {
+        match self {
+            ValueDelta::Set(value) => *target = value,
+            ValueDelta::Remove => *target = Value::Null,
+            ValueDelta::Object(changes) => {
+                if !target.is_object() {
+                    *target = Value::Object(Map::new());
+                }
+                if let Value::Object(map) = target {
+                    for (key, change) in changes {
+                        match change {
+                            ValueDelta::Remove => {
+                                map.remove(&key);
+                            }
+                            change => change.apply(map.entry(key).or_insert(Value::Null)),
+                        }
+                    }
+                }
+            }
+            ValueDelta::Array { len, items } => {
+                if !target.is_array() {
+                    *target = Value::Array(Vec::new());
+                }
+                if let Value::Array(array) = target {
+                    array.resize(len, Value::Null);
+                    for (i, change) in items {
+                        if let Some(item) = array.get_mut(i) {
+                            change.apply(item);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Read the snapshots appended into the dump, compressed files are detected by its content so
+/// the file extension doesn't matter. Deltas are applied so every item is a full snapshot.
+pub struct DumpReader {
+    path: String,
+    lines: Lines<BufReader<Box<dyn Read>>>,
+    line: usize,
+    previous: Option<Value>,
+    since_keyframe: usize,
+}
+
+impl DumpReader {
+    pub fn open(path: &str) -> Result<Self, DumpError> {
+        let file = File::open(path)
+            .map_err(|e| DumpError::Generic(format!("fail to open {}: {}", path, e)))?;
+        let mut buffer = BufReader::new(file);
+
+        let is_gzip = buffer
+            .fill_buf()
+            .map_err(|e| DumpError::Generic(format!("fail to read {}: {}", path, e)))?
+            .starts_with(&GZIP_MAGIC);
+
+        let reader: Box<dyn Read> = if is_gzip {
+            Box::new(MultiGzDecoder::new(buffer))
+        } else {
+            Box::new(buffer)
+        };
+
+        Ok(DumpReader {
+            path: path.to_string(),
+            lines: BufReader::new(reader).lines(),
+            line: 0,
+            previous: None,
+            since_keyframe: 0,
+        })
+    }
+
+    fn parse_error<E: std::fmt::Display>(&self, e: E) -> DumpError {
+        DumpError::Generic(format!(
+            "fail to parse {} line {}: {}",
+            self.path, self.line, e
+        ))
+    }
+
+    /// next snapshot as a JSON document
+    fn next_value(&mut self) -> Option<Result<Value, DumpError>> {
+        let line = match self.lines.next()? {
+            Ok(line) => line,
+            Err(e) => {
+                return Some(Err(DumpError::Generic(format!(
+                    "fail to read {}: {}",
+                    self.path, e
+                ))))
+            }
+        };
+        self.line += 1;
+
+        let value: Value = match serde_json::from_str(&line) {
+            Ok(value) => value,
+            Err(e) => return Some(Err(self.parse_error(e))),
+        };
+
+        let is_record = value
+            .as_object()
+            .map(|o| o.len() == 1 && (o.contains_key("keyframe") || o.contains_key("delta")))
+            .unwrap_or(false);
+
+        let record = if is_record {
+            match serde_json::from_value(value) {
+                Ok(record) => record,
+                Err(e) => return Some(Err(self.parse_error(e))),
+            }
+        } else {
+            DumpRecord::Keyframe(value)
+        };
+
+        let snapshot = match (record, self.previous.take()) {
+            (DumpRecord::Keyframe(value), _) => {
+                self.since_keyframe = 0;
+                value
+            }
+            (DumpRecord::Delta(delta), Some(mut previous)) => {
+                self.since_keyframe += 1;
+                delta.apply(&mut previous);
+                previous
+            }
+            (DumpRecord::Delta(_), None) => {
+                return Some(Err(self.parse_error("delta without a previous keyframe")))
+            }
+        };
+
+        self.previous = Some(snapshot.clone());
+        Some(Ok(snapshot))
+    }
+}
+
+impl Iterator for DumpReader {
+    type Item = Result<AuroraData, DumpError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let value = self.next_value()?;
+        Some(value.and_then(|value| serde_json::from_value(value).map_err(|e| self.parse_error(e))))
+    }
+}
+
+/// Append snapshots into a dump.
+///
+/// A keyframe is written every `keyframe_interval` snapshots and deltas against the previous
+/// snapshot in between, an interval of 1 or less writes only keyframes. The file is reopened on
+/// each write so the writer can be kept while collecting, even when the dump is replaced by a
+/// compaction. When the dump was changed by someone else since the last write, the previous
+/// snapshot may be stale and a keyframe is written instead.
+pub struct DumpWriter {
+    path: String,
+    /// opened with `append`, other writers and compactions may use the dump, so each write holds
+    /// the dump lock
+    shared: bool,
+    format: DumpFormat,
+    keyframe_interval: usize,
+    previous: Option<Value>,
+    since_keyframe: usize,
+    /// size and modification time of the dump after the last write of this writer
+    written: Option<FileState>,
+}
+
+type FileState = (u64, Option<SystemTime>);
+
+fn file_state(metadata: std::io::Result<std::fs::Metadata>) -> Option<FileState> {
+    metadata.ok().map(|m| (m.len(), m.modified().ok()))
+}
+
+impl DumpWriter {
+    /// open the dump for append, creating it when don't exist. The last snapshot is read back
+    /// from the dump so the next delta can be computed.
+    pub fn append(path: &str, keyframe_interval: usize) -> Result<Self, DumpError> {
+        let mut previous = None;
+        let mut since_keyframe = 0;
+
+        let has_data = std::fs::metadata(path)
+            .map(|m| m.len() > 0)
+            .unwrap_or(false);
+        if keyframe_interval > 1 && has_data {
+            let mut reader = DumpReader::open(path)?;
+            while let Some(value) = reader.next_value() {
+                previous = Some(value?);
+            }
+            since_keyframe = reader.since_keyframe;
+        }
+
+        let file = open_append(path)?;
+
+        Ok(DumpWriter {
+            path: path.to_string(),
+            shared: true,
+            written: file_state(file.metadata()),
+            format: DumpFormat::detect(path),
+            keyframe_interval,
+            previous,
+            since_keyframe,
+        })
+    }
+
+    /// create a new dump, replacing any existent file
+    pub fn create(path: &str, keyframe_interval: usize) -> Result<Self, DumpError> {
+        DumpWriter::create_with_format(path, DumpFormat::from_path(path), keyframe_interval)
+    }
+
+    /// create a new dump ignoring the file extension
+    pub fn create_with_format(
+        path: &str,
+        format: DumpFormat,
+        keyframe_interval: usize,
+    ) -> Result<Self, DumpError> {
+        let file = File::create(path)
+            .map_err(|e| DumpError::Generic(format!("fail to create {}: {}", path, e)))?;
+
+        Ok(DumpWriter {
+            path: path.to_string(),
+            shared: false,
+            written: file_state(file.metadata()),
+            format,
+            keyframe_interval,
+            previous: None,
+            since_keyframe: 0,
+        })
+    }
+
+    pub fn write(&mut self, data: &AuroraData) -> Result<(), DumpError> {
+        let value = serde_json::to_value(data)
+            .map_err(|e| DumpError::Generic(format!("fail to serialize data: {}", e)))?;
+
+        let _lock = if self.shared {
+            Some(DumpLock::acquire(&self.path)?)
+        } else {
+            None
+        };
+        if file_state(std::fs::metadata(&self.path)) != self.written {
+            self.previous = None;
+        }
+
+        let record = match &self.previous {
+            Some(previous) if self.since_keyframe + 1 < self.keyframe_interval => {
+                self.since_keyframe += 1;
+                DumpRecord::Delta(
+                    ValueDelta::diff(previous, &value)
+                        .unwrap_or_else(|| ValueDelta::Object(IndexMap::new())),
+                )
+            }
+            _ => {
+                self.since_keyframe = 0;
+                DumpRecord::Keyframe(value.clone())
+            }
+        };
+
+        let mut line = serde_json::to_vec(&record)
+            .map_err(|e| DumpError::Generic(format!("fail to serialize data: {}", e)))?;
+        line.push(b'\n');
+
+        let mut file = open_append(&self.path)?;
+        let result = match self.format {
+            DumpFormat::JsonLines => file.write_all(&line),
+            DumpFormat::GzipJsonLines => {
+                let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+                encoder
+                    .write_all(&line)
+                    .and_then(|_| encoder.finish())
+                    .and_then(|member| file.write_all(&member))
+            }
+        };
+
+        result
+            .and_then(|_| file.flush())
+            .map_err(|e| DumpError::Generic(format!("fail to write dump: {}", e)))?;
+
+        self.written = file_state(file.metadata());
+        self.previous = Some(value);
+        Ok(())
+    }
+}
+
+/// Exclusive lock of a dump, kept in `<dump>.lock` and released when dropped. Appends of shared
+/// writers and the replacement of the dump by a compaction hold it, so they never interleave.
+pub struct DumpLock {
+    _file: File,
+}
+
+impl DumpLock {
+    /// wait until the lock of the dump at `path` is free and take it
+    pub fn acquire(path: &str) -> Result<DumpLock, DumpError> {
+        let lock_path = format!("{}.lock", path);
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&lock_path)
+            .map_err(|e| DumpError::Generic(format!("fail to open {}: {}", lock_path, e)))?;
+        file.lock()
+            .map_err(|e| DumpError::Generic(format!("fail to lock {}: {}", lock_path, e)))?;
+        Ok(DumpLock { _file: file })
+    }
+}
+
+fn open_append(path: &str) -> Result<File, DumpError> {
+    OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(path)
+        .map_err(|e| DumpError::Generic(format!("fail to open {}: {}", path, e)))
+}
+
+/// Append a single snapshot into the dump
+pub fn append(path: &str, data: &AuroraData, keyframe_interval: usize) -> Result<(), DumpError> {
+    DumpWriter::append(path, keyframe_interval)?.write(data)
+}
+
+/// Rewrite all snapshots from `from` into `to`, the output format is defined by the `to`
+/// extension. Returns the number of snapshots converted.
+pub fn convert(from: &str, to: &str, keyframe_interval: usize) -> Result<usize, DumpError> {
+    if from == to {
+        return Err(DumpError::Generic(
+            "input and output dump must be different files".to_string(),
+        ));
+    }
+
+    let reader = DumpReader::open(from)?;
+    let mut writer = DumpWriter::create(to, keyframe_interval)?;
+    let mut count = 0;
+
+    for data in reader {
+        writer.write(&data?)?;
+        count += 1;
+    }
+
+    Ok(count)
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+    use crate::dashboard::test::game_data;
+
+    fn temp_path(name: &str) -> String {
+        std::env::temp_dir()
+            .join(format!("aurora_analytics_{}_{}", std::process::id(), name))
+            .to_string_lossy()
+            .to_string()
+    }
+
+    /// remove a dump written by a shared writer and its lock file
+    pub(crate) fn remove_dump(path: &str) {
+        std::fs::remove_file(path).unwrap();
+        std::fs::remove_file(format!("{}.lock", path)).unwrap();
+    }
+
+    fn snapshot(time: f64) -> AuroraData {
+        AuroraData {
+            games: vec![game_data(time)],
+        }
+    }
+
+    fn read_times(path: &str) -> Vec<f64> {
+        DumpReader::open(path)
+            .unwrap()
+            .map(|data| data.unwrap().games[0].game.game_time)
+            .collect()
+    }
+
+    #[test]
+    fn dump_should_append_and_read_compressed_snapshots() {
+        let path = temp_path("append.json.gz");
+
+        append(&path, &snapshot(1.0), DEFAULT_KEYFRAME_INTERVAL).unwrap();
+        append(&path, &snapshot(2.0), DEFAULT_KEYFRAME_INTERVAL).unwrap();
+
+        let mut file = File::open(&path).unwrap();
+        let mut magic = [0u8; 2];
+        file.read_exact(&mut magic).unwrap();
+        assert_eq!(magic, GZIP_MAGIC);
+
+        assert_eq!(read_times(&path), vec![1.0, 2.0]);
+        remove_dump(&path);
+    }
+
+    #[test]
+    fn dump_should_append_in_the_format_of_existing_dump() {
+        let compressed = temp_path("renamed.json");
+        let mut writer =
+            DumpWriter::create_with_format(&compressed, DumpFormat::GzipJsonLines, 1).unwrap();
+        writer.write(&snapshot(1.0)).unwrap();
+        append(&compressed, &snapshot(2.0), 1).unwrap();
+        assert_eq!(read_times(&compressed), vec![1.0, 2.0]);
+
+        let plain = temp_path("renamed.json.gz");
+        let mut writer = DumpWriter::create_with_format(&plain, DumpFormat::JsonLines, 1).unwrap();
+        writer.write(&snapshot(1.0)).unwrap();
+        append(&plain, &snapshot(2.0), 1).unwrap();
+        assert!(std::fs::read_to_string(&plain).is_ok());
+        assert_eq!(read_times(&plain), vec![1.0, 2.0]);
+
+        remove_dump(&compressed);
+        remove_dump(&plain);
+    }
+
+    #[test]
+    fn dump_should_convert_between_formats() {
+        let plain = temp_path("convert.json");
+        let compressed = temp_path("convert.json.gz");
+
+        append(&plain, &snapshot(1.0), 1).unwrap();
+        append(&plain, &snapshot(2.0), 1).unwrap();
+        append(&plain, &snapshot(3.0), 1).unwrap();
+
+        assert_eq!(
+            convert(&plain, &compressed, DEFAULT_KEYFRAME_INTERVAL).unwrap(),
+            3
+        );
+        assert_eq!(read_times(&compressed), vec![1.0, 2.0, 3.0]);
+
+        let plain_size = std::fs::metadata(&plain).unwrap().len();
+        let compressed_size = std::fs::metadata(&compressed).unwrap().len();
+        assert!(compressed_size < plain_size);
+
+        assert!(convert(&plain, &plain, 1).is_err());
+
+        remove_dump(&plain);
+        std::fs::remove_file(&compressed).unwrap();
+    }
+
+    #[test]
+    fn dump_should_write_deltas_between_keyframes() {
+        let path = temp_path("delta.json");
+        let mut expected = Vec::new();
+
+        for i in 0..5 {
+            let mut data = snapshot(i as f64);
+            *data.games[0].populations[0]
+                .values
+                .get_mut("population")
+                .unwrap() += i as f64;
+            if i == 3 {
+                data.games[0].populations[0].minerals = None;
+            }
+            // reopen the dump on each append to restore the delta state from the file
+            append(&path, &data, 3).unwrap();
+            expected.push(data);
+        }
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        let kinds: Vec<bool> = content
+            .lines()
+            .map(|line| line.starts_with("{\"keyframe\""))
+            .collect();
+        assert_eq!(kinds, vec![true, false, false, true, false]);
+
+        let read: Vec<AuroraData> = DumpReader::open(&path)
+            .unwrap()
+            .map(|d| d.unwrap())
+            .collect();
+        assert_eq!(
+            serde_json::to_value(&read).unwrap(),
+            serde_json::to_value(&expected).unwrap()
+        );
+        // the comparison above ignores the order of the keys
+        let names = |data: &AuroraData| -> Vec<String> {
+            data.games[0].populations[0]
+                .values
+                .keys()
+                .cloned()
+                .collect()
+        };
+        assert!(read
+            .iter()
+            .zip(&expected)
+            .all(|(r, e)| names(r) == names(e)));
+        remove_dump(&path);
+    }
+
+    #[test]
+    fn dump_should_write_keyframe_after_external_append() {
+        let path = temp_path("external.json");
+
+        let mut writer = DumpWriter::append(&path, 10).unwrap();
+        writer.write(&snapshot(1.0)).unwrap();
+        writer.write(&snapshot(2.0)).unwrap();
+        append(&path, &snapshot(3.0), 10).unwrap();
+        writer.write(&snapshot(4.0)).unwrap();
+        writer.write(&snapshot(5.0)).unwrap();
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        let kinds: Vec<bool> = content
+            .lines()
+            .map(|line| line.starts_with("{\"keyframe\""))
+            .collect();
+        assert_eq!(kinds, vec![true, false, false, true, false]);
+        assert_eq!(read_times(&path), vec![1.0, 2.0, 3.0, 4.0, 5.0]);
+        remove_dump(&path);
+    }
+
+    #[test]
+    fn dump_should_wait_for_the_lock_to_append() {
+        let path = temp_path("locked.json");
+        let _ = std::fs::remove_file(&path);
+
+        let lock = DumpLock::acquire(&path).unwrap();
+        let appender = {
+            let path = path.clone();
+            std::thread::spawn(move || append(&path, &snapshot(1.0), 1).unwrap())
+        };
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        assert!(read_times(&path).is_empty());
+
+        drop(lock);
+        appender.join().unwrap();
+        assert_eq!(read_times(&path), vec![1.0]);
+        remove_dump(&path);
+    }
+
+    #[test]
+    fn dump_should_read_plain_snapshots() {
+        let path = temp_path("plain.json");
+        let lines: Vec<String> = (1..3)
+            .map(|i| serde_json::to_string(&snapshot(i as f64)).unwrap())
+            .collect();
+        std::fs::write(&path, lines.join("\n") + "\n").unwrap();
+
+        append(&path, &snapshot(3.0), DEFAULT_KEYFRAME_INTERVAL).unwrap();
+
+        assert_eq!(read_times(&path), vec![1.0, 2.0, 3.0]);
+        remove_dump(&path);
+    }
+}
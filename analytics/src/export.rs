@@ -0,0 +1,207 @@
+use crate::dashboard::{DashboardField, GameDashboard};
+use std::io::Write;
+
+/// Which series of a game are exported
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExportTarget {
+    /// game wide fields, e.g. wealth
+    Game,
+    Population(u32),
+    /// population fields summed over all populations
+    Totals,
+    /// user defined metrics
+    Custom,
+}
+
+#[derive(Debug)]
+pub enum ExportError {
+    NotFound(String),
+    Io(std::io::Error),
+}
+
+impl From<std::io::Error> for ExportError {
+    fn from(e: std::io::Error) -> Self {
+        ExportError::Io(e)
+    }
+}
+
+/// Write the target series of a game as CSV with one row per game time and one column per
+/// field, times where a field has no value are left empty.
+pub fn write_csv<W: Write>(
+    writer: &mut W,
+    game: &GameDashboard,
+    target: ExportTarget,
+) -> Result<(), ExportError> {
+    let totals;
+    let fields: &[DashboardField] = match target {
+        ExportTarget::Game => &game.fields,
+        ExportTarget::Custom => &game.custom,
+        ExportTarget::Totals => {
+            totals = game.totals();
+            &totals
+        }
+        ExportTarget::Population(population_id) => {
+            &game
+                .populations
+                .iter()
+                .find(|pop| pop.population_id == population_id)
+                .ok_or_else(|| {
+                    ExportError::NotFound(format!("population {} not found", population_id))
+                })?
+                .fields
+        }
+    };
+
+    write_fields_csv(writer, game, fields)
+}
+
+pub fn write_fields_csv<W: Write>(
+    writer: &mut W,
+    game: &GameDashboard,
+    fields: &[DashboardField],
+) -> Result<(), ExportError> {
+    let mut times: Vec<f64> = fields
+        .iter()
+        .flat_map(|field| field.historical.iter().map(|value| value.x))
+        .collect();
+    times.sort_by(|a, b| a.total_cmp(b));
+    times.dedup();
+
+    let mut header = vec!["game_time".to_string(), "date".to_string()];
+    header.extend(fields.iter().map(|field| escape(&field.name)));
+    writeln!(writer, "{}", header.join(","))?;
+
+    // historical values are sorted, so keep a cursor per field instead of searching every row
+    let mut cursors = vec![0; fields.len()];
+
+    for time in times {
+        let mut row = vec![time.to_string(), game.date(time).to_iso_datetime()];
+
+        for (field, cursor) in fields.iter().zip(cursors.iter_mut()) {
+            // a game time can be stored more than once, the last value written wins
+            let mut last = None;
+            while let Some(value) = field.historical.get(*cursor).filter(|v| v.x <= time) {
+                if value.x == time {
+                    last = Some(value.y);
+                }
+                *cursor += 1;
+            }
+            row.push(last.map(|y| y.to_string()).unwrap_or_default());
+        }
+
+        writeln!(writer, "{}", row.join(","))?;
+    }
+
+    Ok(())
+}
+
+fn escape(value: &str) -> String {
+    if value.contains(',') || value.contains('"') || value.contains('\n') {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::aurora_db::AuroraData;
+    use crate::dashboard::test::game_data;
+    use crate::dashboard::Dashboard;
+
+    #[test]
+    fn export_population_as_csv() {
+        let mut dashboard = Dashboard::new();
+        dashboard.append(&AuroraData {
+            games: vec![game_data(0.0), game_data(86400.0)],
+        });
+
+        let mut buffer = vec![];
+        write_csv(
+            &mut buffer,
+            &dashboard.games[0],
+            ExportTarget::Population(1),
+        )
+        .unwrap();
+        let csv = String::from_utf8(buffer).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+
+        assert_eq!(lines.len(), 3);
+        assert!(
+            lines[0].starts_with("game_time,date,fuel_stockpile,maintenance_stockpile,population")
+        );
+        assert!(lines[1].starts_with("0,0002-01-01T00:00:00,1,2,3"));
+        assert!(lines[2].starts_with("86400,0002-01-02T00:00:00,1,2,3"));
+    }
+
+    #[test]
+    fn export_should_leave_missing_values_empty() {
+        let mut with_uridium = game_data(2.0);
+        with_uridium.populations[0]
+            .values
+            .insert("uridium".to_string(), 5.0);
+
+        let mut dashboard = Dashboard::new();
+        dashboard.append(&AuroraData {
+            games: vec![game_data(1.0), with_uridium],
+        });
+
+        let mut buffer = vec![];
+        write_csv(
+            &mut buffer,
+            &dashboard.games[0],
+            ExportTarget::Population(1),
+        )
+        .unwrap();
+        let csv = String::from_utf8(buffer).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+
+        assert!(lines[0].ends_with(",uridium"));
+        assert!(lines[1].ends_with(","));
+        assert!(lines[2].ends_with(",5"));
+    }
+
+    #[test]
+    fn export_should_keep_last_value_of_repeated_game_time() {
+        let mut repeated = game_data(1.0);
+        repeated.populations[0]
+            .values
+            .insert("fuel_stockpile".to_string(), 7.0);
+
+        let mut dashboard = Dashboard::new();
+        dashboard.append(&AuroraData {
+            games: vec![game_data(1.0), repeated, game_data(2.0)],
+        });
+
+        let mut buffer = vec![];
+        write_csv(
+            &mut buffer,
+            &dashboard.games[0],
+            ExportTarget::Population(1),
+        )
+        .unwrap();
+        let csv = String::from_utf8(buffer).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+
+        assert_eq!(lines.len(), 3);
+        assert!(lines[1].starts_with("1,0002-01-01T00:00:01,7,2,3"));
+        assert!(lines[2].starts_with("2,0002-01-01T00:00:02,1,2,3"));
+    }
+
+    #[test]
+    fn export_should_fail_for_unknown_population() {
+        let mut dashboard = Dashboard::new();
+        dashboard.append(&AuroraData {
+            games: vec![game_data(1.0)],
+        });
+
+        let mut buffer = vec![];
+        let result = write_csv(
+            &mut buffer,
+            &dashboard.games[0],
+            ExportTarget::Population(9),
+        );
+        assert!(result.is_err());
+    }
+}
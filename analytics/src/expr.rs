@@ -0,0 +1,404 @@
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::fmt;
+
+/// An arithmetic expression over named values, e.g. `population / (fuel_stockpile + 1)`.
+///
+/// Supports numbers, names, `+ - * /`, unary minus and parentheses. Names may contain letters,
+/// digits, `_` and `.` but can't start with a digit. Serialized as its text.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum Expr {
+    Number(f64),
+    Name(String),
+    Neg(Box<Expr>),
+    Binary(Op, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub position: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at position {}", self.message, self.position)
+    }
+}
+
+impl TryFrom<String> for Expr {
+    type Error = ParseError;
+
+    fn try_from(text: String) -> Result<Self, Self::Error> {
+        Expr::parse(&text)
+    }
+}
+
+impl From<Expr> for String {
+    fn from(expr: Expr) -> Self {
+        expr.to_string()
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // operands that are operations themselves are always parenthesized
+        let operand = |f: &mut fmt::Formatter<'_>, expr: &Expr| match expr {
+            Expr::Binary(..) => write!(f, "({})", expr),
+            _ => write!(f, "{}", expr),
+        };
+
+        match self {
+            Expr::Number(value) => write!(f, "{}", value),
+            Expr::Name(name) => write!(f, "{}", name),
+            Expr::Neg(expr) => {
+                write!(f, "-")?;
+                operand(f, expr)
+            }
+            Expr::Binary(op, left, right) => {
+                let op = match op {
+                    Op::Add => "+",
+                    Op::Sub => "-",
+                    Op::Mul => "*",
+                    Op::Div => "/",
+                };
+                operand(f, left)?;
+                write!(f, " {} ", op)?;
+                operand(f, right)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    Name(String),
+    Op(char),
+    Open,
+    Close,
+}
+
+fn tokenize(input: &str) -> Result<Vec<(usize, Token)>, ParseError> {
+    let chars: Vec<char> = input.chars().collect();
+    let mut tokens = vec![];
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        let start = i;
+
+        if c.is_whitespace() {
+            i += 1;
+        } else if c.is_ascii_digit()
+            || (c == '.' && chars.get(i + 1).is_some_and(|c| c.is_ascii_digit()))
+        {
+            while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                i += 1;
+            }
+            // exponent, e.g. 1e6 or 2.5E-3
+            if i < chars.len() && (chars[i] == 'e' || chars[i] == 'E') {
+                let mut j = i + 1;
+                if j < chars.len() && (chars[j] == '+' || chars[j] == '-') {
+                    j += 1;
+                }
+                if j < chars.len() && chars[j].is_ascii_digit() {
+                    i = j;
+                    while i < chars.len() && chars[i].is_ascii_digit() {
+                        i += 1;
+                    }
+                }
+            }
+            let text: String = chars[start..i].iter().collect();
+            let value = text.parse().map_err(|_| ParseError {
+                position: start,
+                message: format!("invalid number {}", text),
+            })?;
+            tokens.push((start, Token::Number(value)));
+        } else if c.is_alphabetic() || c == '_' {
+            while i < chars.len()
+                && (chars[i].is_alphanumeric() || chars[i] == '_' || chars[i] == '.')
+            {
+                i += 1;
+            }
+            tokens.push((start, Token::Name(chars[start..i].iter().collect())));
+        } else {
+            let token = match c {
+                '+' | '-' | '*' | '/' => Token::Op(c),
+                '(' => Token::Open,
+                ')' => Token::Close,
+                _ => {
+                    return Err(ParseError {
+                        position: start,
+                        message: format!("unexpected character {}", c),
+                    })
+                }
+            };
+            tokens.push((start, token));
+            i += 1;
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// Deeper expressions are a parse error instead of a stack overflow when parsed, evaluated or
+/// dropped. Counts parentheses, unary minus and every operation of a chain like `a + b + c`.
+const MAX_DEPTH: usize = 256;
+
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    index: usize,
+    end: usize,
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.index).map(|(_, token)| token)
+    }
+
+    fn position(&self) -> usize {
+        self.tokens
+            .get(self.index)
+            .map(|(position, _)| *position)
+            .unwrap_or(self.end)
+    }
+
+    fn error<T>(&self, message: &str) -> Result<T, ParseError> {
+        Err(ParseError {
+            position: self.position(),
+            message: message.to_string(),
+        })
+    }
+
+    /// run `parse` one nesting level deeper
+    fn nested<T>(
+        &mut self,
+        parse: impl FnOnce(&mut Self) -> Result<T, ParseError>,
+    ) -> Result<T, ParseError> {
+        if self.depth >= MAX_DEPTH {
+            return self.error("expression nested too deeply");
+        }
+        self.depth += 1;
+        let result = parse(self);
+        self.depth -= 1;
+        result
+    }
+
+    /// `left op right`, failing when the operations of a chain get too deep
+    fn binary(&self, op: Op, left: Expr, right: Expr) -> Result<Expr, ParseError> {
+        let expr = Expr::Binary(op, Box::new(left), Box::new(right));
+        if expr.depth() > MAX_DEPTH {
+            return self.error("expression nested too deeply");
+        }
+        Ok(expr)
+    }
+
+    /// expression := term (('+' | '-') term)*
+    fn expression(&mut self) -> Result<Expr, ParseError> {
+        let mut left = self.term()?;
+        while let Some(Token::Op(c)) = self.peek() {
+            let op = match c {
+                '+' => Op::Add,
+                '-' => Op::Sub,
+                _ => break,
+            };
+            self.index += 1;
+            let right = self.term()?;
+            left = self.binary(op, left, right)?;
+        }
+        Ok(left)
+    }
+
+    /// term := factor (('*' | '/') factor)*
+    fn term(&mut self) -> Result<Expr, ParseError> {
+        let mut left = self.factor()?;
+        while let Some(Token::Op(c)) = self.peek() {
+            let op = match c {
+                '*' => Op::Mul,
+                '/' => Op::Div,
+                _ => break,
+            };
+            self.index += 1;
+            let right = self.factor()?;
+            left = self.binary(op, left, right)?;
+        }
+        Ok(left)
+    }
+
+    /// factor := number | name | '-' factor | '(' expression ')'
+    fn factor(&mut self) -> Result<Expr, ParseError> {
+        let token = match self.peek() {
+            Some(token) => token.clone(),
+            None => return self.error("unexpected end of expression"),
+        };
+        self.index += 1;
+
+        match token {
+            Token::Number(value) => Ok(Expr::Number(value)),
+            Token::Name(name) => Ok(Expr::Name(name)),
+            Token::Op('-') => Ok(Expr::Neg(Box::new(self.nested(Self::factor)?))),
+            Token::Open => {
+                let expr = self.nested(Self::expression)?;
+                match self.peek() {
+                    Some(Token::Close) => {
+                        self.index += 1;
+                        Ok(expr)
+                    }
+                    _ => self.error("expected )"),
+                }
+            }
+            _ => {
+                self.index -= 1;
+                self.error("expected a number, a name or (")
+            }
+        }
+    }
+}
+
+impl Expr {
+    pub fn parse(input: &str) -> Result<Expr, ParseError> {
+        let mut parser = Parser {
+            tokens: tokenize(input)?,
+            index: 0,
+            end: input.chars().count(),
+            depth: 0,
+        };
+
+        let expr = parser.expression()?;
+        if parser.peek().is_some() {
+            return parser.error("unexpected token");
+        }
+        Ok(expr)
+    }
+
+    /// levels of the expression tree, bounded by `MAX_DEPTH` for parsed expressions
+    fn depth(&self) -> usize {
+        match self {
+            Expr::Number(_) | Expr::Name(_) => 1,
+            Expr::Neg(expr) => expr.depth() + 1,
+            Expr::Binary(_, left, right) => left.depth().max(right.depth()) + 1,
+        }
+    }
+
+    /// names used by the expression
+    pub fn names(&self) -> Vec<&str> {
+        match self {
+            Expr::Number(_) => vec![],
+            Expr::Name(name) => vec![name.as_str()],
+            Expr::Neg(expr) => expr.names(),
+            Expr::Binary(_, left, right) => {
+                let mut names = left.names();
+                names.extend(right.names());
+                names
+            }
+        }
+    }
+
+    /// Evaluate with the values returned by `lookup`, `None` when a name has no value or the
+    /// result isn't a finite number, e.g. a division by zero
+    pub fn eval<F>(&self, lookup: &F) -> Option<f64>
+    where
+        F: Fn(&str) -> Option<f64>,
+    {
+        let value = match self {
+            Expr::Number(value) => *value,
+            Expr::Name(name) => lookup(name)?,
+            Expr::Neg(expr) => -expr.eval(lookup)?,
+            Expr::Binary(op, left, right) => {
+                let left = left.eval(lookup)?;
+                let right = right.eval(lookup)?;
+                match op {
+                    Op::Add => left + right,
+                    Op::Sub => left - right,
+                    Op::Mul => left * right,
+                    Op::Div => left / right,
+                }
+            }
+        };
+
+        Some(value).filter(|value| value.is_finite())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn eval(input: &str) -> Option<f64> {
+        let lookup = |name: &str| match name {
+            "population" => Some(10.0),
+            "fuel_stockpile" => Some(4.0),
+            "deposits.1" => Some(2.0),
+            _ => None,
+        };
+        Expr::parse(input).unwrap().eval(&lookup)
+    }
+
+    #[test]
+    fn expr_should_respect_precedence() {
+        assert_eq!(eval("1 + 2 * 3"), Some(7.0));
+        assert_eq!(eval("(1 + 2) * 3"), Some(9.0));
+        assert_eq!(eval("10 - 4 - 3"), Some(3.0));
+        assert_eq!(eval("-2 * -3"), Some(6.0));
+        assert_eq!(eval("1.5e2 / .5"), Some(300.0));
+    }
+
+    #[test]
+    fn expr_should_use_names() {
+        assert_eq!(eval("population / (fuel_stockpile + 1)"), Some(2.0));
+        assert_eq!(eval("deposits.1 * population"), Some(20.0));
+        assert_eq!(eval("population + missing"), None);
+        assert_eq!(eval("population / 0"), None);
+
+        let expr = Expr::parse("a + b * a").unwrap();
+        assert_eq!(expr.names(), vec!["a", "b", "a"]);
+        assert_eq!(expr.to_string(), "a + (b * a)");
+        assert_eq!(Expr::parse(&expr.to_string()).unwrap(), expr);
+    }
+
+    #[test]
+    fn expr_should_report_syntax_errors() {
+        assert_eq!(Expr::parse("1 +").unwrap_err().position, 3);
+        assert_eq!(Expr::parse("(1 + 2").unwrap_err().message, "expected )");
+        assert_eq!(Expr::parse("1 2").unwrap_err().position, 2);
+        assert_eq!(Expr::parse("2 $ 3").unwrap_err().position, 2);
+        assert!(Expr::parse("").is_err());
+        assert!(Expr::parse("* 2").is_err());
+    }
+
+    #[test]
+    fn expr_should_limit_the_nesting_depth() {
+        let nested = |depth: usize| format!("{}1{}", "(".repeat(depth), ")".repeat(depth));
+        assert_eq!(eval(&nested(MAX_DEPTH)), Some(1.0));
+        assert_eq!(
+            Expr::parse(&nested(MAX_DEPTH + 1)).unwrap_err().message,
+            "expression nested too deeply"
+        );
+        assert!(Expr::parse(&nested(100_000)).is_err());
+        assert!(Expr::parse(&format!("{}1", "-".repeat(100_000))).is_err());
+    }
+
+    #[test]
+    fn expr_should_limit_the_length_of_operation_chains() {
+        let chain = |terms: usize, op: &str| vec!["population"; terms].join(op);
+        assert_eq!(eval(&chain(MAX_DEPTH, "+")), Some(10.0 * MAX_DEPTH as f64));
+        assert_eq!(
+            Expr::parse(&chain(MAX_DEPTH + 1, "+")).unwrap_err().message,
+            "expression nested too deeply"
+        );
+        assert!(Expr::parse(&chain(200_000, "+")).is_err());
+        assert!(Expr::parse(&chain(200_000, "*")).is_err());
+        assert!(Expr::parse(&format!("-({})", chain(200_000, " - "))).is_err());
+    }
+}
@@ -0,0 +1,445 @@
+use crate::aurora_db::{AuroraData, DbError};
+use crate::aurora_schema::AuroraSchema;
+use indexmap::IndexMap;
+use rusqlite::types::ToSql;
+use rusqlite::Connection;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// Dashboard level of the values read from a table
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Scope {
+    /// one value per game, rows are summed
+    Game,
+    /// one value per population, identified by the `key` column, rows are summed by population
+    #[default]
+    Population,
+}
+
+/// Columns of a Aurora table tracked as dashboard fields
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TableMap {
+    pub table: String,
+    pub scope: Scope,
+    /// column with the population id, required for the population scope
+    #[serde(default)]
+    pub key: Option<String>,
+    /// column filtered by the game id
+    #[serde(default)]
+    pub game_column: Option<String>,
+    /// column filtered by the player race id
+    #[serde(default)]
+    pub race_column: Option<String>,
+    /// value column and the dashboard field name it's stored as
+    pub fields: IndexMap<String, String>,
+}
+
+/// Columns to read on every snapshot.
+///
+/// Values are stored in the `values` of the game or population and become dashboard fields. The
+/// built-in fields are read by `FieldMap::builtin`, extra ones are loaded from a TOML or JSON file.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct FieldMap {
+    #[serde(default)]
+    pub tables: Vec<TableMap>,
+}
+
+fn quote(identifier: &str) -> String {
+    format!("\"{}\"", identifier.replace('"', "\"\""))
+}
+
+impl TableMap {
+    /// key and filter columns, the table can't be read without them
+    fn required_columns(&self) -> Vec<&String> {
+        self.key
+            .iter()
+            .chain(self.game_column.iter())
+            .chain(self.race_column.iter())
+            .collect()
+    }
+
+    fn validate(&self) -> Result<(), DbError> {
+        if self.scope == Scope::Population && self.key.is_none() {
+            return Err(DbError::Generic(format!(
+                "field map of {} requires a key column for the population scope",
+                self.table
+            )));
+        }
+        Ok(())
+    }
+
+    /// select of the available value columns with their column and field names, `None` when
+    /// nothing can be read
+    fn query(&self, schema: &AuroraSchema) -> Option<(String, Vec<(&String, &String)>)> {
+        if !schema.has_table(&self.table)
+            || self
+                .required_columns()
+                .iter()
+                .any(|column| !schema.has_column(&self.table, column))
+        {
+            return None;
+        }
+
+        let fields: Vec<(&String, &String)> = self
+            .fields
+            .iter()
+            .filter(|(column, _)| schema.has_column(&self.table, column))
+            .collect();
+        if fields.is_empty() {
+            return None;
+        }
+
+        let mut columns: Vec<String> = vec![];
+        if let Some(key) = &self.key {
+            columns.push(quote(key));
+        }
+        columns.extend(fields.iter().map(|(column, _)| quote(column)));
+
+        let mut filters: Vec<String> = vec![];
+        if let Some(column) = &self.game_column {
+            filters.push(format!("{} = :game_id", quote(column)));
+        }
+        if let Some(column) = &self.race_column {
+            filters.push(format!("{} = :race_id", quote(column)));
+        }
+
+        let mut sql = format!("select {} from {}", columns.join(", "), quote(&self.table));
+        if !filters.is_empty() {
+            sql = format!("{} where {}", sql, filters.join(" and "));
+        }
+
+        Some((sql, fields))
+    }
+}
+
+/// read a configuration file, JSON when the extension is `.json` and TOML otherwise
+pub(crate) fn load_file<T: DeserializeOwned>(path: &str) -> Result<T, DbError> {
+    if !Path::new(path).exists() {
+        return Err(DbError::Generic(format!("{} not found", path)));
+    }
+
+    let body = std::fs::read_to_string(path)
+        .map_err(|e| DbError::Generic(format!("fail to open {}: {}", path, e)))?;
+    // parsed directly, a figment would sort the keys and lose the order of the fields
+    if path.ends_with(".json") {
+        serde_json::from_str(&body)
+            .map_err(|e| DbError::Generic(format!("fail to parse {}: {}", path, e)))
+    } else {
+        toml::from_str(&body)
+            .map_err(|e| DbError::Generic(format!("fail to parse {}: {}", path, e)))
+    }
+}
+
+impl FieldMap {
+    /// Fields read from every Aurora database, the player race wealth and the population
+    /// stockpiles. Columns missing in older databases are skipped like the ones of any map.
+    pub fn builtin() -> FieldMap {
+        fn fields(columns: &[(&str, &str)]) -> IndexMap<String, String> {
+            columns
+                .iter()
+                .map(|(column, name)| (column.to_string(), name.to_string()))
+                .collect()
+        }
+
+        FieldMap {
+            tables: vec![
+                TableMap {
+                    table: "FCT_Race".to_string(),
+                    scope: Scope::Game,
+                    key: None,
+                    game_column: Some("GameID".to_string()),
+                    race_column: Some("RaceID".to_string()),
+                    fields: fields(&[
+                        ("WealthPoints", "wealth"),
+                        ("AnnualWealth", "annual_wealth"),
+                    ]),
+                },
+                TableMap {
+                    table: "FCT_Population".to_string(),
+                    scope: Scope::Population,
+                    key: Some("PopulationID".to_string()),
+                    game_column: None,
+                    race_column: Some("RaceID".to_string()),
+                    fields: fields(&[
+                        ("FuelStockpile", "fuel_stockpile"),
+                        ("MaintenanceStockpile", "maintenance_stockpile"),
+                        ("Population", "population"),
+                        ("Duranium", "duranium"),
+                        ("Neutronium", "neutronium"),
+                        ("Corbomite", "corbomite"),
+                        ("Tritanium", "tritanium"),
+                        ("Boronide", "boronide"),
+                        ("Mercassium", "mercassium"),
+                        ("Vendarite", "vendarite"),
+                        ("Sorium", "sorium"),
+                        ("Uridium", "uridium"),
+                        ("Corundium", "corundium"),
+                        ("Gallicite", "gallicite"),
+                    ]),
+                },
+            ],
+        }
+    }
+
+    /// dashboard field names read into the games or the populations, in declaration order
+    pub fn names(&self, scope: Scope) -> Vec<String> {
+        let mut names: Vec<String> = vec![];
+        for table in self.tables.iter().filter(|table| table.scope == scope) {
+            for name in table.fields.values() {
+                if !names.contains(name) {
+                    names.push(name.clone());
+                }
+            }
+        }
+        names
+    }
+
+    /// load the map from `path`, JSON when the extension is `.json` and TOML otherwise
+    pub fn load(path: &str) -> Result<FieldMap, DbError> {
+        let map: FieldMap = load_file(path)?;
+        for table in &map.tables {
+            table.validate()?;
+        }
+        Ok(map)
+    }
+
+    /// `table.column` of the map that the database doesn't have
+    pub fn unavailable(&self, schema: &AuroraSchema) -> Vec<String> {
+        let mut unavailable = vec![];
+        for table in &self.tables {
+            if !schema.has_table(&table.table) {
+                unavailable.push(table.table.clone());
+                continue;
+            }
+            let columns = table
+                .required_columns()
+                .into_iter()
+                .chain(table.fields.keys());
+            for column in columns {
+                if !schema.has_column(&table.table, column) {
+                    unavailable.push(format!("{}.{}", table.table, column));
+                }
+            }
+        }
+        unavailable
+    }
+
+    /// Read the mapped values of every game in `data`, missing tables and columns are skipped.
+    /// Values that aren't numbers are skipped too and returned as errors, one by column.
+    pub fn fetch(
+        &self,
+        connection: &Connection,
+        schema: &AuroraSchema,
+        data: &mut AuroraData,
+    ) -> Result<Vec<DbError>, DbError> {
+        let mut errors = vec![];
+
+        for table in &self.tables {
+            let (sql, fields) = match table.query(schema) {
+                Some(query) => query,
+                None => continue,
+            };
+            let mut stmt = connection
+                .prepare(&sql)
+                .map_err(|e| DbError::Generic(format!("query of {} failed: {}", table.table, e)))?;
+            // first error of each column that isn't a number
+            let mut invalid: BTreeMap<&String, rusqlite::Error> = BTreeMap::new();
+
+            for agame in data.games.iter_mut() {
+                let game_id = agame.game.game_id;
+                let race_id = agame.race_id;
+                let mut params: Vec<(&str, &dyn ToSql)> = vec![];
+                if table.game_column.is_some() {
+                    params.push((":game_id", &game_id));
+                }
+                if table.race_column.is_some() {
+                    params.push((":race_id", &race_id));
+                }
+
+                let offset = if table.key.is_some() { 1 } else { 0 };
+                type Checked<T> = Result<Option<T>, rusqlite::Error>;
+                let rows: Vec<(Checked<u32>, Vec<Checked<f64>>)> = stmt
+                    .query_map_named(&params, |row| {
+                        let key = if table.key.is_some() {
+                            row.get_checked(0)
+                        } else {
+                            Ok(None)
+                        };
+                        let values = (0..fields.len())
+                            .map(|i| row.get_checked(i + offset))
+                            .collect();
+                        (key, values)
+                    })
+                    .and_then(|rows| rows.collect::<Result<Vec<_>, _>>())
+                    .map_err(|e| {
+                        DbError::Generic(format!("query of {} failed: {}", table.table, e))
+                    })?;
+
+                for (key, values) in rows {
+                    let key = match key {
+                        Ok(key) => key,
+                        Err(e) => {
+                            invalid.entry(table.key.as_ref().unwrap()).or_insert(e);
+                            continue;
+                        }
+                    };
+
+                    let target = match (table.scope, key) {
+                        (Scope::Game, _) => Some(&mut agame.values),
+                        (Scope::Population, Some(key)) => agame
+                            .populations
+                            .iter_mut()
+                            .find(|pop| pop.population_id == key)
+                            .map(|pop| &mut pop.values),
+                        (Scope::Population, None) => None,
+                    };
+
+                    if let Some(target) = target {
+                        for ((column, name), value) in fields.iter().zip(values) {
+                            match value {
+                                Ok(Some(value)) => {
+                                    *target.entry(name.to_string()).or_insert(0.0) += value
+                                }
+                                Ok(None) => {}
+                                Err(e) => {
+                                    invalid.entry(column).or_insert(e);
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+
+            errors.extend(invalid.into_iter().map(|(column, e)| {
+                DbError::Generic(format!("{}.{} skipped: {}", table.table, column, e))
+            }));
+        }
+
+        Ok(errors)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::dashboard::test::game_data;
+
+    #[test]
+    fn field_map_should_keep_declaration_order() {
+        assert_eq!(
+            FieldMap::builtin().names(Scope::Game),
+            vec!["wealth".to_string(), "annual_wealth".to_string()]
+        );
+
+        let path = std::env::temp_dir().join(format!(
+            "aurora_analytics_{}_field_map.toml",
+            std::process::id()
+        ));
+        std::fs::write(
+            &path,
+            "[[tables]]
+             table = \"FCT_Population\"
+             scope = \"population\"
+             key = \"PopulationID\"
+             fields = { Stability = \"stability\", Colonists = \"colonists\" }",
+        )
+        .unwrap();
+
+        let map = FieldMap::load(path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            map.names(Scope::Population),
+            vec!["stability".to_string(), "colonists".to_string()]
+        );
+    }
+
+    #[test]
+    fn field_map_should_read_mapped_columns() {
+        let connection = Connection::open_in_memory().unwrap();
+        connection
+            .execute_batch(
+                "create table FCT_Population (PopulationID, RaceID, Colonists, Stability);
+                 insert into FCT_Population values (1, 4, 100, 0.5);
+                 insert into FCT_Population values (2, 4, 50, 1);
+                 insert into FCT_Population values (1, 5, 999, 1);
+                 create table FCT_Ship (GameID, RaceID, Tonnage);
+                 insert into FCT_Ship values (0, 4, 1000);
+                 insert into FCT_Ship values (0, 4, 500);",
+            )
+            .unwrap();
+        let schema = AuroraSchema::detect(&connection).unwrap();
+
+        let map: FieldMap = serde_json::from_str(
+            r#"{"tables": [
+                {"table": "FCT_Population", "scope": "population", "key": "PopulationID",
+                 "race_column": "RaceID",
+                 "fields": {"Colonists": "colonists", "Stability": "stability", "Missing": "x"}},
+                {"table": "FCT_Ship", "scope": "game", "game_column": "GameID",
+                 "race_column": "RaceID", "fields": {"Tonnage": "tonnage"}},
+                {"table": "FCT_Missing", "scope": "game", "fields": {"A": "a"}}
+            ]}"#,
+        )
+        .unwrap();
+
+        let mut data = AuroraData {
+            games: vec![game_data(1.0)],
+        };
+        assert!(map
+            .fetch(&connection, &schema, &mut data)
+            .unwrap()
+            .is_empty());
+
+        let game = &data.games[0];
+        assert_eq!(game.values.get("tonnage"), Some(&1500.0));
+        let pop = &game.populations[0];
+        assert_eq!(pop.values.get("colonists"), Some(&100.0));
+        assert_eq!(pop.values.get("stability"), Some(&0.5));
+
+        assert_eq!(
+            map.unavailable(&schema),
+            vec![
+                "FCT_Population.Missing".to_string(),
+                "FCT_Missing".to_string()
+            ]
+        );
+    }
+
+    #[test]
+    fn field_map_should_report_values_that_are_not_numbers() {
+        let connection = Connection::open_in_memory().unwrap();
+        connection
+            .execute_batch(
+                "create table FCT_Race (RaceID, GameID, NPR, WealthPoints, AnnualWealth);
+                 insert into FCT_Race values (4, 0, 0, 'rich', 10);
+                 create table FCT_Population (PopulationID, RaceID, Population, Sorium);
+                 insert into FCT_Population values (1, 4, 'many', 5);",
+            )
+            .unwrap();
+        let schema = AuroraSchema::detect(&connection).unwrap();
+
+        let mut data = AuroraData {
+            games: vec![game_data(1.0)],
+        };
+        data.games[0].values.clear();
+        data.games[0].populations[0].values.clear();
+        let errors = FieldMap::builtin()
+            .fetch(&connection, &schema, &mut data)
+            .unwrap();
+
+        let messages: Vec<String> = errors.iter().map(|e| format!("{:?}", e)).collect();
+        assert_eq!(messages.len(), 2, "{:?}", messages);
+        assert!(messages[0].contains("FCT_Race.WealthPoints"));
+        assert!(messages[1].contains("FCT_Population.Population"));
+
+        let game = &data.games[0];
+        assert_eq!(game.values.get("wealth"), None);
+        assert_eq!(game.values.get("annual_wealth"), Some(&10.0));
+        let pop = &game.populations[0].values;
+        assert_eq!(pop.get("population"), None);
+        assert_eq!(pop.get("sorium"), Some(&5.0));
+        assert_eq!(pop.get("gallicite"), None);
+    }
+}
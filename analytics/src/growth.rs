@@ -0,0 +1,225 @@
+use crate::calendar::SECONDS_PER_YEAR;
+use crate::dashboard::{GameDashboard, HistoricalValue};
+use serde::{Deserialize, Serialize};
+
+/// game years projected when not specified
+pub const DEFAULT_YEARS: f64 = 5.0;
+/// longest projection, in game years, a bigger one would only allocate a huge series
+pub const MAX_YEARS: f64 = 100.0;
+/// colonies above this share of their estimated capacity are flagged
+pub const NEAR_CAPACITY: f64 = 0.9;
+/// projected values per game year
+const STEPS_PER_YEAR: usize = 4;
+/// values needed to fit a model
+const MIN_VALUES: usize = 3;
+
+/// Growth of a series, exponential or logistic when it slows down toward a capacity.
+///
+/// The model goes through the last known value so projections continue the history.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct GrowthModel {
+    /// growth rate per game year, e.g. 0.05 for 5%
+    pub rate: f64,
+    /// estimated maximum, `None` when the growth is exponential
+    pub capacity: Option<f64>,
+    /// last known value `(x, y)`
+    anchor: (f64, f64),
+}
+
+/// least squares line `(intercept, slope)`
+fn linear_fit(points: &[(f64, f64)]) -> Option<(f64, f64)> {
+    let n = points.len() as f64;
+    let mean_x = points.iter().map(|(x, _)| x).sum::<f64>() / n;
+    let mean_y = points.iter().map(|(_, y)| y).sum::<f64>() / n;
+    let sxx: f64 = points.iter().map(|(x, _)| (x - mean_x).powi(2)).sum();
+    let sxy: f64 = points
+        .iter()
+        .map(|(x, y)| (x - mean_x) * (y - mean_y))
+        .sum();
+
+    if sxx <= 0.0 {
+        return None;
+    }
+    let slope = sxy / sxx;
+    Some((mean_y - slope * mean_x, slope))
+}
+
+impl GrowthModel {
+    /// Fit the model on `values`, `None` without enough positive values.
+    ///
+    /// The exponential growth is fitted on the log of the values. The logistic growth is fitted
+    /// for capacities from just above the largest value to 100 times it, and only kept when it's
+    /// clearly better than the exponential one.
+    pub fn fit(values: &[HistoricalValue]) -> Option<GrowthModel> {
+        let values: Vec<&HistoricalValue> = values.iter().filter(|v| v.y > 0.0).collect();
+        if values.len() < MIN_VALUES {
+            return None;
+        }
+
+        let last = values[values.len() - 1];
+        // years relative to the last value
+        let points: Vec<(f64, f64)> = values
+            .iter()
+            .map(|v| ((v.x - last.x) / SECONDS_PER_YEAR, v.y))
+            .collect();
+        let max = points.iter().map(|(_, y)| *y).fold(0.0, f64::max);
+
+        let error = |predict: &dyn Fn(f64) -> f64| -> f64 {
+            points.iter().map(|(t, y)| (predict(*t) - y).powi(2)).sum()
+        };
+
+        let log_points: Vec<(f64, f64)> = points.iter().map(|(t, y)| (*t, y.ln())).collect();
+        let (intercept, rate) = linear_fit(&log_points)?;
+        let exponential_error = error(&|t| (intercept + rate * t).exp());
+        let mut best = GrowthModel {
+            rate,
+            capacity: None,
+            anchor: (last.x, last.y),
+        };
+
+        // ln(K / y - 1) = ln(A) - r t
+        const STEPS: i32 = 60;
+        let mut logistic: Option<(f64, f64, f64)> = None;
+        for step in 0..STEPS {
+            let capacity = max * 1.001 * (100.0_f64 / 1.001).powf(step as f64 / (STEPS - 1) as f64);
+            let logit: Vec<(f64, f64)> = points
+                .iter()
+                .map(|(t, y)| (*t, (capacity / y - 1.0).ln()))
+                .collect();
+            let (intercept, slope) = match linear_fit(&logit) {
+                Some(fit) => fit,
+                None => continue,
+            };
+            if slope >= 0.0 {
+                // not growing
+                continue;
+            }
+
+            let e = error(&|t| capacity / (1.0 + (intercept + slope * t).exp()));
+            if step < STEPS - 1 && logistic.is_none_or(|(_, _, best)| e < best) {
+                logistic = Some((capacity, -slope, e));
+            }
+        }
+
+        if let Some((capacity, rate, e)) = logistic {
+            if e < exponential_error * 0.9 {
+                best.rate = rate;
+                best.capacity = Some(capacity);
+            }
+        }
+
+        Some(best)
+    }
+
+    /// value expected at game time `x`
+    pub fn predict(&self, x: f64) -> f64 {
+        let (x0, y0) = self.anchor;
+        let t = (x - x0) / SECONDS_PER_YEAR;
+        match self.capacity {
+            None => y0 * (self.rate * t).exp(),
+            Some(capacity) => capacity / (1.0 + (capacity / y0 - 1.0) * (-self.rate * t).exp()),
+        }
+    }
+
+    /// values from the last known one to `years` game years later
+    pub fn project(&self, years: f64) -> Vec<HistoricalValue> {
+        let (x0, _) = self.anchor;
+        let steps = (years.max(0.0) * STEPS_PER_YEAR as f64).ceil() as usize;
+        (0..=steps)
+            .map(|step| {
+                let x = x0 + step as f64 * SECONDS_PER_YEAR / STEPS_PER_YEAR as f64;
+                HistoricalValue {
+                    x,
+                    y: self.predict(x),
+                }
+            })
+            .collect()
+    }
+
+    /// true when the last known value is close to the capacity
+    pub fn near_capacity(&self) -> bool {
+        self.capacity
+            .is_some_and(|capacity| self.anchor.1 >= capacity * NEAR_CAPACITY)
+    }
+}
+
+/// Growth model and projection of a colony
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Projection {
+    pub population_id: u32,
+    pub population_name: String,
+    pub model: GrowthModel,
+    pub near_capacity: bool,
+    pub projected: Vec<HistoricalValue>,
+}
+
+/// check `years` is a projection length between 0 and `MAX_YEARS`
+pub fn validate_years(years: f64) -> Result<(), String> {
+    if !(0.0..=MAX_YEARS).contains(&years) {
+        return Err(format!(
+            "years must be between 0 and {}, got {}",
+            MAX_YEARS, years
+        ));
+    }
+    Ok(())
+}
+
+/// projections of the `population` field of every colony with enough history
+pub fn project_game(game: &GameDashboard, years: f64) -> Vec<Projection> {
+    game.populations
+        .iter()
+        .filter_map(|pop| {
+            let field = pop.fields.iter().find(|f| f.name == "population")?;
+            let model = GrowthModel::fit(&field.historical)?;
+            Some(Projection {
+                population_id: pop.population_id,
+                population_name: pop.population_name.clone(),
+                model,
+                near_capacity: model.near_capacity(),
+                projected: model.project(years),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn series(f: impl Fn(f64) -> f64) -> Vec<HistoricalValue> {
+        (0..20)
+            .map(|i| {
+                let years = i as f64 * 0.5;
+                HistoricalValue {
+                    x: years * SECONDS_PER_YEAR,
+                    y: f(years),
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn growth_should_fit_exponential_and_logistic_series() {
+        let exponential = GrowthModel::fit(&series(|t| 10.0 * (0.1 * t).exp())).unwrap();
+        assert!((exponential.rate - 0.1).abs() < 0.001);
+        assert_eq!(exponential.capacity, None);
+        assert!(!exponential.near_capacity());
+        let projected = exponential.project(2.0);
+        assert_eq!(projected.len(), 9);
+        let last = 10.0 * (0.1 * 11.5_f64).exp();
+        assert!((projected[8].y - last).abs() / last < 0.001);
+
+        let logistic =
+            GrowthModel::fit(&series(|t| 1000.0 / (1.0 + 99.0 * (-0.8 * t).exp()))).unwrap();
+        let capacity = logistic.capacity.unwrap();
+        assert!((capacity - 1000.0).abs() < 100.0, "capacity {}", capacity);
+        assert!((logistic.rate - 0.8).abs() < 0.1, "rate {}", logistic.rate);
+        assert!(logistic.near_capacity());
+        assert!(logistic.project(50.0).iter().all(|v| v.y <= capacity));
+
+        assert!(GrowthModel::fit(&series(|_| 0.0)).is_none());
+        let flat = GrowthModel::fit(&series(|_| 5.0)).unwrap();
+        assert_eq!(flat.capacity, None);
+        assert_eq!(flat.predict(100.0 * SECONDS_PER_YEAR), 5.0);
+    }
+}
@@ -0,0 +1,392 @@
+use crate::alerts::Alert;
+use crate::collector::SnapshotEvent;
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+
+/// longest time a hook can take, commands are killed afterwards
+const TIMEOUT: Duration = Duration::from_secs(5);
+/// interval between two checks of a running command
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+#[derive(Debug)]
+pub enum HookError {
+    Generic(String),
+}
+
+/// Payload sent to the hooks, tagged with `"event": "snapshot"` or `"event": "alert"`
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum HookEvent {
+    Snapshot(SnapshotEvent),
+    Alert(Alert),
+}
+
+impl HookEvent {
+    fn name(&self) -> &'static str {
+        match self {
+            HookEvent::Snapshot(_) => "snapshot",
+            HookEvent::Alert(_) => "alert",
+        }
+    }
+}
+
+/// Notifications of new snapshots and alerts, nothing is sent when both are absent
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct HookCfg {
+    /// shell command run with the JSON payload on stdin and the event name in `AURORA_EVENT`
+    pub command: Option<String>,
+    /// `http://` URL on a loopback address receiving the JSON payload as a POST
+    pub url: Option<String>,
+}
+
+impl HookCfg {
+    /// send `event` to every configured hook, a failing hook doesn't stop the others
+    pub fn notify(&self, event: &HookEvent) -> Vec<HookError> {
+        let payload = match serde_json::to_string(event) {
+            Ok(payload) => payload,
+            Err(e) => return vec![HookError::Generic(format!("fail to serialize: {}", e))],
+        };
+
+        let mut errors = vec![];
+        if let Some(command) = &self.command {
+            if let Err(e) = run_command(command, event.name(), &payload) {
+                errors.push(e);
+            }
+        }
+        if let Some(url) = &self.url {
+            if let Err(e) = post(url, &payload) {
+                errors.push(e);
+            }
+        }
+        errors
+    }
+}
+
+fn run_command(command: &str, event: &str, payload: &str) -> Result<(), HookError> {
+    let error = |e: String| HookError::Generic(format!("command {} failed: {}", command, e));
+
+    let mut shell = if cfg!(windows) {
+        let mut shell = Command::new("cmd");
+        shell.arg("/C");
+        shell
+    } else {
+        let mut shell = Command::new("sh");
+        shell.arg("-c");
+        shell
+    };
+    // in its own group so the processes started by the command are killed with it
+    #[cfg(unix)]
+    std::os::unix::process::CommandExt::process_group(&mut shell, 0);
+
+    let mut child = shell
+        .arg(command)
+        .env("AURORA_EVENT", event)
+        .stdin(Stdio::piped())
+        .spawn()
+        .map_err(|e| error(e.to_string()))?;
+
+    // written from another thread as a command not reading its input would block the write once
+    // the pipe is full
+    if let Some(mut stdin) = child.stdin.take() {
+        let payload = payload.to_string();
+        std::thread::spawn(move || {
+            // the command may not read its input
+            let _ = stdin.write_all(payload.as_bytes());
+        });
+    }
+
+    let started = Instant::now();
+    let status = loop {
+        match child.try_wait().map_err(|e| error(e.to_string()))? {
+            Some(status) => break status,
+            None if started.elapsed() >= TIMEOUT => {
+                kill_tree(&mut child);
+                let _ = child.wait();
+                return Err(error(format!("killed after {:?}", TIMEOUT)));
+            }
+            None => std::thread::sleep(POLL_INTERVAL),
+        }
+    };
+
+    if !status.success() {
+        return Err(error(status.to_string()));
+    }
+    Ok(())
+}
+
+/// kill the shell of a command and every process it started
+fn kill_tree(child: &mut Child) {
+    #[cfg(unix)]
+    // SAFETY: kill only sends a signal, the group id is the shell pid as set by `process_group`
+    unsafe {
+        libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL);
+    }
+    #[cfg(windows)]
+    let _ = Command::new("taskkill")
+        .args(["/T", "/F", "/PID", &child.id().to_string()])
+        .status();
+    let _ = child.kill();
+}
+
+/// host, port and path of a `http://` URL, IPv6 hosts are written in brackets, e.g.
+/// `http://[::1]:8000/` and returned without them
+fn parse_url(url: &str) -> Result<(String, u16, String), HookError> {
+    let rest = url.strip_prefix("http://").ok_or_else(|| {
+        HookError::Generic(format!("unsupported url {}, only http is supported", url))
+    })?;
+
+    let (authority, path) = match rest.find('/') {
+        Some(index) => (&rest[..index], rest[index..].to_string()),
+        None => (rest, "/".to_string()),
+    };
+
+    let invalid_host = || HookError::Generic(format!("invalid host in {}", url));
+    let (host, port) = match authority.strip_prefix('[') {
+        Some(bracketed) => {
+            let (host, rest) = bracketed.split_once(']').ok_or_else(invalid_host)?;
+            match rest {
+                "" => (host, None),
+                rest => (host, Some(rest.strip_prefix(':').ok_or_else(invalid_host)?)),
+            }
+        }
+        None => match authority.rsplit_once(':') {
+            Some((host, port)) => (host, Some(port)),
+            None => (authority, None),
+        },
+    };
+    let port = match port {
+        Some(port) => port
+            .parse()
+            .map_err(|_| HookError::Generic(format!("invalid port in {}", url)))?,
+        None => 80,
+    };
+
+    if host.is_empty() {
+        return Err(HookError::Generic(format!("missing host in {}", url)));
+    }
+    Ok((host.to_string(), port, path))
+}
+
+fn post(url: &str, payload: &str) -> Result<(), HookError> {
+    let error = |e: String| HookError::Generic(format!("post to {} failed: {}", url, e));
+
+    let (host, port, path) = parse_url(url)?;
+    let address = (host.as_str(), port)
+        .to_socket_addrs()
+        .map_err(|e| error(e.to_string()))?
+        .next()
+        .ok_or_else(|| error("unknown host".to_string()))?;
+    // snapshots are only sent to a local service, e.g. a bot running next to Aurora
+    if !address.ip().is_loopback() {
+        return Err(error(format!("{} is not a loopback address", address.ip())));
+    }
+
+    let mut stream =
+        TcpStream::connect_timeout(&address, TIMEOUT).map_err(|e| error(e.to_string()))?;
+    stream
+        .set_read_timeout(Some(TIMEOUT))
+        .and_then(|_| stream.set_write_timeout(Some(TIMEOUT)))
+        .map_err(|e| error(e.to_string()))?;
+
+    // IPv6 hosts keep their brackets in the header
+    let host_header = if host.contains(':') {
+        format!("[{}]:{}", host, port)
+    } else {
+        format!("{}:{}", host, port)
+    };
+    let request = format!(
+        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        path,
+        host_header,
+        payload.len(),
+        payload
+    );
+    stream
+        .write_all(request.as_bytes())
+        .map_err(|e| error(e.to_string()))?;
+
+    // only the status line matters, e.g. `HTTP/1.1 204 No Content`
+    let mut status_line = String::new();
+    BufReader::new(stream)
+        .read_line(&mut status_line)
+        .map_err(|e| error(e.to_string()))?;
+    let status = status_line.split_whitespace().nth(1).unwrap_or_default();
+    if !status.starts_with('2') {
+        return Err(error(format!("unexpected response {}", status_line.trim())));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Read;
+    use std::net::TcpListener;
+
+    fn snapshot() -> HookEvent {
+        HookEvent::Snapshot(SnapshotEvent {
+            game_id: 1,
+            game_time: 2.0,
+            date: "2025-01-01T00:00:02".to_string(),
+            populations: vec![3],
+        })
+    }
+
+    /// answer one request on `address` with `status` and return the received body
+    fn stub_server(
+        address: &str,
+        status: &'static str,
+    ) -> (String, std::thread::JoinHandle<String>) {
+        let listener = TcpListener::bind(address).unwrap();
+        let address = listener.local_addr().unwrap();
+        let url = format!("http://{}/hooks/aurora", address);
+
+        let handle = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+
+            let mut length = 0;
+            let mut head = String::new();
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if let Some(value) = line.strip_prefix("Content-Length: ") {
+                    length = value.trim().parse().unwrap();
+                }
+                head.push_str(&line);
+                if line == "\r\n" {
+                    break;
+                }
+            }
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+
+            let response = format!("HTTP/1.1 {}\r\nContent-Length: 0\r\n\r\n", status);
+            reader.get_mut().write_all(response.as_bytes()).unwrap();
+
+            assert!(head.starts_with("POST /hooks/aurora HTTP/1.1\r\n"));
+            assert!(head.contains(&format!("\r\nHost: {}\r\n", address)));
+            String::from_utf8(body).unwrap()
+        });
+
+        (url, handle)
+    }
+
+    #[test]
+    fn hooks_should_post_events() {
+        let (url, server) = stub_server("127.0.0.1:0", "200 OK");
+        let hooks = HookCfg {
+            command: None,
+            url: Some(url),
+        };
+        assert!(hooks.notify(&snapshot()).is_empty());
+
+        let body: serde_json::Value = serde_json::from_str(&server.join().unwrap()).unwrap();
+        assert_eq!(body["event"], "snapshot");
+        assert_eq!(body["game_id"], 1);
+        assert_eq!(body["populations"][0], 3);
+
+        let (url, server) = stub_server("127.0.0.1:0", "500 Internal Server Error");
+        let hooks = HookCfg {
+            command: None,
+            url: Some(url),
+        };
+        assert_eq!(hooks.notify(&snapshot()).len(), 1);
+        server.join().unwrap();
+
+        let remote = HookCfg {
+            command: None,
+            url: Some("http://10.1.2.3:8000/hooks".to_string()),
+        };
+        assert_eq!(remote.notify(&snapshot()).len(), 1);
+
+        let (url, server) = stub_server("[::1]:0", "204 No Content");
+        let ipv6 = HookCfg {
+            command: None,
+            url: Some(url),
+        };
+        assert!(ipv6.notify(&snapshot()).is_empty());
+        server.join().unwrap();
+
+        assert!(parse_url("https://localhost/").is_err());
+        assert_eq!(
+            parse_url("http://localhost").unwrap(),
+            ("localhost".to_string(), 80, "/".to_string())
+        );
+        assert_eq!(
+            parse_url("http://[::1]:8000/hooks").unwrap(),
+            ("::1".to_string(), 8000, "/hooks".to_string())
+        );
+        assert_eq!(parse_url("http://[::1]").unwrap().1, 80);
+        assert!(parse_url("http://[::1/").is_err());
+        assert!(parse_url("http://[::1]8000/").is_err());
+        assert!(parse_url("http://localhost:/").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn hooks_should_run_commands() {
+        let output = std::env::temp_dir().join(format!(
+            "aurora_analytics_{}_hook_event.json",
+            std::process::id()
+        ));
+
+        let hooks = HookCfg {
+            command: Some(format!(
+                "echo $AURORA_EVENT > {0} && cat >> {0}",
+                output.display()
+            )),
+            url: None,
+        };
+        assert!(hooks.notify(&snapshot()).is_empty());
+
+        let written = std::fs::read_to_string(&output).unwrap();
+        let (event, payload) = written.split_once('\n').unwrap();
+        assert_eq!(event, "snapshot");
+        assert!(payload.starts_with("{\"event\":\"snapshot\""));
+        std::fs::remove_file(&output).unwrap();
+
+        let failing = HookCfg {
+            command: Some("exit 3".to_string()),
+            url: None,
+        };
+        assert_eq!(failing.notify(&snapshot()).len(), 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn hooks_should_kill_commands_after_timeout() {
+        let pid_file =
+            std::env::temp_dir().join(format!("aurora_analytics_{}_hook_pid", std::process::id()));
+        // the shell waits for a process it started, both must be killed
+        let hooks = HookCfg {
+            command: Some(format!("sleep 30 & echo $! > {}; wait", pid_file.display())),
+            url: None,
+        };
+
+        let started = Instant::now();
+        let errors = hooks.notify(&snapshot());
+        assert_eq!(errors.len(), 1);
+        assert!(started.elapsed() < Duration::from_secs(10));
+
+        #[cfg(target_os = "linux")]
+        {
+            let pid = std::fs::read_to_string(&pid_file).unwrap();
+            // a killed process stays a zombie until its new parent reaps it
+            let running = || {
+                std::fs::read_to_string(format!("/proc/{}/stat", pid.trim()))
+                    .map(|stat| !stat.contains(") Z "))
+                    .unwrap_or(false)
+            };
+            let killed = Instant::now();
+            while running() && killed.elapsed() < Duration::from_secs(2) {
+                std::thread::sleep(POLL_INTERVAL);
+            }
+            assert!(!running());
+        }
+        std::fs::remove_file(&pid_file).unwrap();
+    }
+}
@@ -1,3 +1,21 @@
+pub mod alerts;
+pub mod anomalies;
+pub mod archive;
 pub mod aurora_db;
+pub mod aurora_schema;
+pub mod calendar;
 pub mod collector;
+pub mod compare;
+pub mod config;
 pub mod dashboard;
+pub mod dump;
+pub mod export;
+pub mod expr;
+pub mod field_map;
+pub mod growth;
+pub mod hooks;
+pub mod metrics;
+pub mod parquet_export;
+pub mod report;
+pub mod retention;
+pub mod server;
@@ -1,130 +1,622 @@
-#![feature(proc_macro_hygiene, decl_macro)]
+use analytics::archive;
+use analytics::collector::{self, CollectorCfg};
+use analytics::compare::{self, Comparison};
+use analytics::config::{Config, ConfigOverrides};
+use analytics::dashboard::DashboardDb;
+use analytics::dump;
+use analytics::export::{self, ExportTarget};
+use analytics::growth;
+use analytics::parquet_export;
+use analytics::report;
+use analytics::retention::RetentionPolicy;
+use analytics::server;
+use clap::{Parser, Subcommand, ValueEnum};
+use std::io::Write;
+use std::path::Path;
+use std::process::ExitCode;
 
-#[macro_use]
-extern crate rocket;
+/// exit code when the configuration is invalid or incomplete
+const EXIT_CONFIG: u8 = 3;
+/// exit code when the command fails
+const EXIT_FAILURE: u8 = 1;
 
-use analytics::dashboard::Dashboard;
-use rocket::response::content;
-use serde::Serialize;
+/// Collect Aurora 4x game data on every save and serve it as a dashboard
+#[derive(Parser, Debug)]
+#[command(name = "aurora-analytics", version)]
+struct Cli {
+    #[command(flatten)]
+    overrides: ConfigOverrides,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Read the Aurora database once, append it into the dump and rebuild the dashboard
+    Snapshot,
+    /// Watch the Aurora database and take a snapshot every time the game is saved
+    Watch,
+    /// Rebuild the dashboard from all snapshots in the dump
+    RebuildDashboard,
+    /// Run the web server
+    Serve {
+        /// also watch the Aurora database and update the served dashboard on every save
+        #[arg(long)]
+        watch: bool,
+    },
+    /// Export dashboard data of a game or population
+    Export {
+        /// game to export, all games when absent
+        #[arg(long)]
+        game: Option<u32>,
+        /// population to export, requires --game
+        #[arg(long, requires = "game", conflicts_with = "totals")]
+        population: Option<u32>,
+        /// export population fields summed over all populations, requires --game
+        #[arg(long, requires = "game")]
+        totals: bool,
+        /// export the user defined metrics, requires --game
+        #[arg(long, requires = "game", conflicts_with_all = ["population", "totals"])]
+        custom: bool,
+        #[arg(long, value_enum, default_value_t = ExportFormat::Json)]
+        format: ExportFormat,
+        /// output file, stdout when absent
+        #[arg(long, short)]
+        output: Option<String>,
+    },
+    /// Print a summary of the dashboard
+    Inspect,
+    /// Rewrite the dump into another file, the format is chosen by the output extension,
+    /// `.gz` files are gzip compressed
+    ConvertDump {
+        /// new dump file, e.g. data/aurora_dump.json.gz
+        output: String,
+    },
+    /// Apply the retention policy, old snapshots of the dump are replaced by a snapshot with the
+    /// averages of each game year and the dashboard is rebuilt with the same averages. Stop
+    /// `serve --watch` first, it would write its dashboard over the compacted one
+    Compact {
+        /// game years kept at full detail [default: retention_years of the configuration]
+        #[arg(long)]
+        years: Option<u32>,
+    },
+    /// Import every AuroraDB backup (`*.db`) of a folder into the dump, ordered by game time, and
+    /// rebuild the dashboard
+    Backfill {
+        /// folder with the database backups, e.g. the archive folder
+        dir: String,
+    },
+    /// List unusual jumps of the game and population fields compared with their recent trend
+    Anomalies {
+        #[arg(long)]
+        game: u32,
+        /// only list the anomalies of this population
+        #[arg(long)]
+        population: Option<u32>,
+        /// deviation from the trend in standard deviations [default: anomaly_threshold of the
+        /// configuration]
+        #[arg(long)]
+        threshold: Option<f64>,
+    },
+    /// Estimate the growth of every colony and project its population, colonies close to their
+    /// estimated capacity are flagged
+    Projections {
+        #[arg(long)]
+        game: u32,
+        /// game years to project, at most 100
+        #[arg(long, default_value_t = growth::DEFAULT_YEARS)]
+        years: f64,
+    },
+    /// Compare a metric of several games aligned by elapsed game years since their start
+    Compare {
+        /// game field, custom metric or empire total, e.g. population or wealth
+        #[arg(long)]
+        metric: String,
+        /// games to compare, repeat for several games, every game when absent
+        #[arg(long)]
+        game: Vec<u32>,
+        /// game years between two rows, at least 0.1
+        #[arg(long, default_value_t = 1.0)]
+        step: f64,
+    },
+    /// Render a self-contained HTML report of the campaigns with summary tables, charts and the
+    /// alerts and anomalies timeline
+    Report {
+        /// games in the report, repeat for several games, every game when absent
+        #[arg(long)]
+        game: Vec<u32>,
+        /// HTML file written, stdout when absent
+        #[arg(long)]
+        output: Option<String>,
+    },
+    /// List or restore the copies of the Aurora database archived on every save
+    Archive {
+        #[command(subcommand)]
+        command: ArchiveCommand,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum ArchiveCommand {
+    /// List the archives, oldest first
+    List,
+    /// Replace the Aurora database by an archive, the current one is kept as AuroraDB.db.bak
+    Restore {
+        /// archive file name as shown by `archive list`
+        name: String,
+    },
+}
 
-#[derive(Responder)]
-#[response(status = 500, content_type = "json")]
-struct Generic500 {
-    error: String,
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum ExportFormat {
+    Json,
+    /// one row per game time and one column per field, requires --game
+    Csv,
+    /// raw snapshots of the dump as games, populations and mineral_deposits parquet files,
+    /// requires --output folder
+    Parquet,
 }
 
-impl From<std::io::Error> for Generic500 {
-    fn from(e: std::io::Error) -> Self {
-        Generic500 {
-            error: format!("io error {:?}", e),
+#[derive(Debug)]
+enum CliError {
+    Config(String),
+    Failure(String),
+}
+
+impl CliError {
+    fn exit_code(&self) -> ExitCode {
+        match self {
+            CliError::Config(_) => ExitCode::from(EXIT_CONFIG),
+            CliError::Failure(_) => ExitCode::from(EXIT_FAILURE),
         }
     }
 }
 
-impl From<serde_json::Error> for Generic500 {
-    fn from(e: serde_json::Error) -> Self {
-        Generic500 {
-            error: format!("parser error {:?}", e),
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+
+    match run(cli) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            match &e {
+                CliError::Config(msg) => eprintln!("invalid configuration: {}", msg),
+                CliError::Failure(msg) => eprintln!("error: {}", msg),
+            }
+            e.exit_code()
         }
     }
 }
 
-#[get("/")]
-fn index() -> Result<content::Html<String>, Generic500> {
-    let body = std::fs::read_to_string("analytics/resources/index.html")?;
-    Ok(content::Html(body))
+fn run(cli: Cli) -> Result<(), CliError> {
+    let config = Config::load(&cli.overrides).map_err(|e| CliError::Config(format!("{:?}", e)))?;
+
+    match cli.command {
+        Command::Snapshot => {
+            let db_path = db_path(&config)?;
+            println!("processing {}", db_path);
+            let mut writer = dump::DumpWriter::append(&config.dump_path, config.keyframe_interval)
+                .map_err(failure)?;
+            collector::append_output(&config.aurora_db().map_err(config_error)?, &mut writer)
+                .map_err(failure)?;
+            collector::convert_into_dashboard(
+                &config.dump_path,
+                &config.dashboard_path,
+                &config.derived,
+                &config.alerts,
+            )
+            .map(|_| ())
+            .map_err(failure)
+        }
+        Command::Watch => {
+            let db_path = db_path(&config)?;
+            println!("monitoring {}", db_path);
+            let archive = config.archive_cfg();
+            let hooks = config.hooks();
+            collector::collect(CollectorCfg {
+                aurora_db: config.aurora_db().map_err(config_error)?,
+                dump_path: config.dump_path,
+                dashboard_path: config.dashboard_path,
+                keyframe_interval: config.keyframe_interval,
+                archive,
+                derived: config.derived,
+                alerts: config.alerts,
+                hooks,
+            })
+            .map_err(failure)
+        }
+        Command::RebuildDashboard => collector::convert_into_dashboard(
+            &config.dump_path,
+            &config.dashboard_path,
+            &config.derived,
+            &config.alerts,
+        )
+        .map(|_| ())
+        .map_err(failure),
+        Command::Serve { watch } => server::serve(config, watch).map_err(failure),
+        Command::Export {
+            game,
+            population,
+            totals,
+            custom,
+            format,
+            output,
+        } => match format {
+            ExportFormat::Json if totals || custom => Err(CliError::Failure(
+                "--totals and --custom are only available for csv".to_string(),
+            )),
+            ExportFormat::Json => export(&config, game, population, output),
+            ExportFormat::Parquet if game.is_some() || population.is_some() || totals || custom => {
+                Err(CliError::Failure(
+                    "parquet exports every game of the dump, --game, --population, --totals and --custom are not available"
+                        .to_string(),
+                ))
+            }
+            ExportFormat::Parquet => {
+                let output = output.ok_or_else(|| {
+                    CliError::Failure("--output folder is required for parquet".to_string())
+                })?;
+                parquet_export::export_dump(&config.dump_path, Path::new(&output)).map_err(failure)
+            }
+            ExportFormat::Csv => {
+                let target = match (population, totals, custom) {
+                    (Some(population_id), _, _) => ExportTarget::Population(population_id),
+                    (None, true, _) => ExportTarget::Totals,
+                    (None, false, true) => ExportTarget::Custom,
+                    (None, false, false) => ExportTarget::Game,
+                };
+                let game = game
+                    .ok_or_else(|| CliError::Failure("--game is required for csv".to_string()))?;
+                export_csv(&config, game, target, output)
+            }
+        },
+        Command::Inspect => inspect(&config),
+        Command::ConvertDump { output } => {
+            let count = dump::convert(&config.dump_path, &output, config.keyframe_interval)
+                .map_err(failure)?;
+            println!("{} snapshots written at {}", count, output);
+            Ok(())
+        }
+        Command::Compact { years } => compact(&config, years),
+        Command::Backfill { dir } => {
+            let aurora_db = config.aurora_db_template().map_err(config_error)?;
+            let mut writer = dump::DumpWriter::append(&config.dump_path, config.keyframe_interval)
+                .map_err(failure)?;
+            let stats = collector::backfill(&dir, &aurora_db, &mut writer, &config.dump_path)
+                .map_err(failure)?;
+
+            for (path, error) in &stats.failed {
+                eprintln!("fail to read {}: {}", path, error);
+            }
+            println!(
+                "{} databases read, {} snapshots appended, {} already in the dump",
+                stats.databases, stats.appended, stats.skipped
+            );
+
+            collector::convert_into_dashboard(
+                &config.dump_path,
+                &config.dashboard_path,
+                &config.derived,
+                &config.alerts,
+            )
+            .map(|_| ())
+            .map_err(failure)
+        }
+        Command::Anomalies {
+            game,
+            population,
+            threshold,
+        } => anomalies(&config, game, population, threshold),
+        Command::Projections { game, years } => projections(&config, game, years),
+        Command::Compare { metric, game, step } => compare(&config, &metric, &game, step),
+        Command::Report { game, output } => write_report(&config, &game, output.as_deref()),
+        Command::Archive { command } => {
+            let archive_dir = config
+                .archive_path
+                .clone()
+                .ok_or_else(|| CliError::Config("archive_path must be provided".to_string()))?;
+
+            match command {
+                ArchiveCommand::List => {
+                    for entry in archive::list(&archive_dir).map_err(failure)? {
+                        let modified: chrono::DateTime<chrono::Local> = entry.modified.into();
+                        println!(
+                            "{}  {}  {:.1} MB",
+                            modified.format("%Y-%m-%d %H:%M:%S"),
+                            entry.name,
+                            entry.size as f64 / (1024.0 * 1024.0)
+                        );
+                    }
+                    Ok(())
+                }
+                ArchiveCommand::Restore { name } => {
+                    let db_path = db_path(&config)?;
+                    let path = archive::restore(&archive_dir, &name, &db_path).map_err(failure)?;
+                    println!("{} restored from {}", db_path, path.display());
+                    Ok(())
+                }
+            }
+        }
+    }
 }
 
-#[get("/data")]
-fn data() -> Result<content::Json<String>, Generic500> {
-    let body = std::fs::read_to_string("data/dashboard_data.json")?;
-    Ok(content::Json(body))
+fn failure<E: std::fmt::Debug>(e: E) -> CliError {
+    CliError::Failure(format!("{:?}", e))
 }
 
-fn get_data() -> Result<Dashboard, Generic500> {
-    let body = std::fs::read_to_string("data/dashboard_data.json")?;
-    let dashboard = serde_json::from_str(body.as_str())?;
-    Ok(dashboard)
+fn db_path(config: &Config) -> Result<String, CliError> {
+    config
+        .db_path()
+        .map_err(|e| CliError::Config(format!("{:?}", e)))
 }
 
-#[derive(Serialize, Debug)]
-pub struct KeyValudDto<'a> {
-    id: u32,
-    name: &'a str,
+fn config_error<E: std::fmt::Debug>(e: E) -> CliError {
+    CliError::Config(format!("{:?}", e))
 }
 
-#[get("/games")]
-fn data_games() -> Result<content::Json<String>, Generic500> {
-    let dashboard = get_data()?;
-    let result: Vec<KeyValudDto> = dashboard
-        .games
-        .iter()
-        .map(|game| KeyValudDto {
-            id: game.game_id,
-            name: game.game_name.as_str(),
-        })
-        .collect();
+fn export(
+    config: &Config,
+    game_id: Option<u32>,
+    population_id: Option<u32>,
+    output: Option<String>,
+) -> Result<(), CliError> {
+    let dashboard = DashboardDb::load(&config.dashboard_path).map_err(failure)?;
 
-    let result_json = serde_json::to_string_pretty(&result)?;
-    Ok(content::Json(result_json))
+    let json = match (game_id, population_id) {
+        (None, _) => serde_json::to_string_pretty(&dashboard),
+        (Some(game_id), population_id) => {
+            let game = dashboard
+                .games
+                .iter()
+                .find(|game| game.game_id == game_id)
+                .ok_or_else(|| CliError::Failure(format!("game {} not found", game_id)))?;
+
+            match population_id {
+                None => serde_json::to_string_pretty(game),
+                Some(population_id) => {
+                    let pop = game
+                        .populations
+                        .iter()
+                        .find(|pop| pop.population_id == population_id)
+                        .ok_or_else(|| {
+                            CliError::Failure(format!("population {} not found", population_id))
+                        })?;
+                    serde_json::to_string_pretty(pop)
+                }
+            }
+        }
+    }
+    .map_err(failure)?;
+
+    match output {
+        Some(path) => std::fs::write(&path, json).map_err(failure),
+        None => {
+            println!("{}", json);
+            Ok(())
+        }
+    }
 }
 
-#[get("/games/<game_id>/populations")]
-fn data_games_populations(game_id: u32) -> Result<content::Json<String>, Generic500> {
-    let dashboard = get_data()?;
-    let result: Vec<KeyValudDto> = dashboard
+fn export_csv(
+    config: &Config,
+    game_id: u32,
+    target: ExportTarget,
+    output: Option<String>,
+) -> Result<(), CliError> {
+    let dashboard = DashboardDb::load(&config.dashboard_path).map_err(failure)?;
+    let game = dashboard
         .games
         .iter()
         .find(|game| game.game_id == game_id)
-        .map(|game| {
-            game.populations
-                .iter()
-                .map(|pop| KeyValudDto {
-                    id: pop.population_id,
-                    name: pop.population_name.as_str(),
-                })
-                .collect()
-        })
-        .unwrap_or(Vec::new());
+        .ok_or_else(|| CliError::Failure(format!("game {} not found", game_id)))?;
+
+    let mut writer: Box<dyn Write> = match output {
+        Some(path) => Box::new(std::fs::File::create(&path).map_err(failure)?),
+        None => Box::new(std::io::stdout()),
+    };
 
-    let result_json = serde_json::to_string_pretty(&result)?;
-    Ok(content::Json(result_json))
+    export::write_csv(&mut writer, game, target).map_err(failure)
 }
 
-#[get("/games/<game_id>/populations/<population_id>")]
-fn data_games_populations_by_id(
+fn compact(config: &Config, years: Option<u32>) -> Result<(), CliError> {
+    let years = years.or(config.retention_years).ok_or_else(|| {
+        CliError::Config("--years or retention_years must be provided".to_string())
+    })?;
+    let policy = RetentionPolicy::new(years);
+
+    let stats = policy
+        .apply_to_dump(&config.dump_path, config.keyframe_interval)
+        .map_err(failure)?;
+    println!(
+        "{} of {} snapshots kept in {}",
+        stats.after, stats.before, config.dump_path
+    );
+
+    let mut dashboard = DashboardDb::load_aurora_dump(&config.dump_path).map_err(failure)?;
+    dashboard.derive(&config.derived);
+    dashboard.evaluate_alerts(&config.alerts);
+    policy.apply_to_dashboard(&mut dashboard);
+    DashboardDb::save(&dashboard, &config.dashboard_path).map_err(failure)
+}
+
+fn anomalies(
+    config: &Config,
     game_id: u32,
-    population_id: u32,
-) -> Result<content::Json<String>, Generic500> {
-    let dashboard = get_data()?;
+    population_id: Option<u32>,
+    threshold: Option<f64>,
+) -> Result<(), CliError> {
+    let dashboard = DashboardDb::load(&config.dashboard_path).map_err(failure)?;
+    let game = dashboard
+        .games
+        .iter()
+        .find(|game| game.game_id == game_id)
+        .ok_or_else(|| CliError::Failure(format!("game {} not found", game_id)))?;
+
+    let mut cfg = config.anomaly_cfg();
+    if let Some(threshold) = threshold {
+        cfg.threshold = threshold;
+    }
+    cfg.validate().map_err(CliError::Failure)?;
 
-    let result = dashboard
+    let anomalies = cfg.detect(game);
+    let anomalies = anomalies
+        .iter()
+        .filter(|anomaly| population_id.is_none() || anomaly.population_id == population_id);
+    for anomaly in anomalies {
+        println!(
+            "{} {} {}: {} -> {} (expected change {:.2}, score {:.1})",
+            anomaly.date,
+            anomaly
+                .population_name
+                .as_deref()
+                .unwrap_or(&game.game_name),
+            anomaly.field,
+            anomaly.previous,
+            anomaly.value,
+            anomaly.expected,
+            anomaly.score
+        );
+    }
+    Ok(())
+}
+
+fn projections(config: &Config, game_id: u32, years: f64) -> Result<(), CliError> {
+    growth::validate_years(years).map_err(|e| CliError::Failure(format!("--{}", e)))?;
+    let dashboard = DashboardDb::load(&config.dashboard_path).map_err(failure)?;
+    let game = dashboard
         .games
         .iter()
         .find(|game| game.game_id == game_id)
+        .ok_or_else(|| CliError::Failure(format!("game {} not found", game_id)))?;
+
+    for projection in growth::project_game(game, years) {
+        let model = &projection.model;
+        let (first, last) = match (projection.projected.first(), projection.projected.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => continue,
+        };
+        let capacity = match model.capacity {
+            Some(capacity) => format!("capacity {:.2}", capacity),
+            None => "no capacity estimated".to_string(),
+        };
+        println!(
+            "{}: {:.2} -> {:.2} in {} years, growth rate {:.3} per year, {}{}",
+            projection.population_name,
+            first.y,
+            last.y,
+            years,
+            model.rate,
+            capacity,
+            if projection.near_capacity {
+                ", near capacity"
+            } else {
+                ""
+            }
+        );
+    }
+    Ok(())
+}
+
+fn compare(config: &Config, metric: &str, game_ids: &[u32], step: f64) -> Result<(), CliError> {
+    compare::validate_step(step).map_err(|e| CliError::Failure(format!("--{}", e)))?;
+
+    let dashboard = DashboardDb::load(&config.dashboard_path).map_err(failure)?;
+    for game_id in game_ids {
+        if !dashboard.games.iter().any(|game| game.game_id == *game_id) {
+            return Err(CliError::Failure(format!("game {} not found", game_id)));
+        }
+    }
+
+    let comparison = Comparison::new(&dashboard, metric, game_ids);
+    let width = comparison
+        .games
         .iter()
-        .flat_map(|game| {
-            game.populations
-                .iter()
-                .find(|pop| pop.population_id == population_id)
-        })
-        .next()
-        .unwrap();
-
-    let result_json = serde_json::to_string_pretty(result)?;
-    Ok(content::Json(result_json))
-}
-
-#[launch]
-fn rocket() -> _ {
-    rocket::build().mount(
-        "/",
-        routes![
-            index,
-            data,
-            data_games,
-            data_games_populations,
-            data_games_populations_by_id
-        ],
-    )
+        .map(|game| game.game_name.len())
+        .fold(12, usize::max);
+
+    print!("{:>8}", "years");
+    for game in &comparison.games {
+        print!("  {:>width$}", game.game_name, width = width);
+    }
+    println!();
+
+    let table = comparison
+        .table(step)
+        .map_err(|e| CliError::Failure(format!("--{}", e)))?;
+    let decimals = compare::step_decimals(step);
+    for (years, values) in table {
+        print!("{:>8.*}", decimals, years);
+        for value in values {
+            let value = value.map(|v| format!("{:.2}", v)).unwrap_or_default();
+            print!("  {:>width$}", value, width = width);
+        }
+        println!();
+    }
+    Ok(())
+}
+
+fn write_report(config: &Config, game_ids: &[u32], output: Option<&str>) -> Result<(), CliError> {
+    let dashboard = DashboardDb::load(&config.dashboard_path).map_err(failure)?;
+    for game_id in game_ids {
+        if !dashboard.games.iter().any(|game| game.game_id == *game_id) {
+            return Err(CliError::Failure(format!("game {} not found", game_id)));
+        }
+    }
+
+    let html = report::render(&dashboard, game_ids, &config.anomaly_cfg());
+    match output {
+        Some(path) => {
+            std::fs::write(path, html)
+                .map_err(|e| CliError::Failure(format!("fail to write {}: {}", path, e)))?;
+            println!("report written to {}", path);
+        }
+        None => print!("{}", html),
+    }
+    Ok(())
+}
+
+fn inspect(config: &Config) -> Result<(), CliError> {
+    if let Ok(db_path) = config.db_path() {
+        let queries = config
+            .aurora_db()
+            .map_err(config_error)?
+            .query_set()
+            .map_err(failure)?;
+        println!("aurora database {}", db_path);
+        if !queries.unavailable.is_empty() {
+            println!("  unavailable: {}", queries.unavailable.join(", "));
+        }
+    }
+
+    let dashboard = DashboardDb::load(&config.dashboard_path).map_err(failure)?;
+
+    for game in &dashboard.games {
+        let times: Vec<f64> = game
+            .populations
+            .iter()
+            .flat_map(|pop| pop.fields.iter())
+            .flat_map(|field| field.historical.iter().map(|value| value.x))
+            .collect();
+
+        let first = times.iter().cloned().fold(f64::INFINITY, f64::min);
+        let last = times.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+
+        println!("game {} - {}", game.game_id, game.game_name);
+        if !times.is_empty() {
+            println!("  from {} to {}", game.date(first), game.date(last));
+        }
+        println!("  populations: {}", game.populations.len());
+        for pop in &game.populations {
+            let points = pop
+                .fields
+                .first()
+                .map(|field| field.historical.len())
+                .unwrap_or(0);
+            println!(
+                "    {} - {} ({} snapshots)",
+                pop.population_id, pop.population_name, points
+            );
+        }
+    }
+
+    Ok(())
 }
@@ -0,0 +1,239 @@
+use crate::aurora_db::{AuroraData, DbError};
+use crate::field_map;
+use rusqlite::types::{ToSql, Value};
+use rusqlite::{Connection, Row};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+/// A SQL query evaluated on every snapshot.
+///
+/// The query can use the `:game_id` and `:race_id` parameters and return either a single number
+/// or rows of key and number, each key is stored as `name.key`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CustomMetric {
+    pub name: String,
+    pub sql: String,
+}
+
+/// User defined metrics, loaded from a TOML or JSON file
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct MetricSet {
+    #[serde(default)]
+    pub metrics: Vec<CustomMetric>,
+}
+
+impl MetricSet {
+    pub fn load(path: &str) -> Result<MetricSet, DbError> {
+        let metrics: MetricSet = field_map::load_file(path)?;
+        metrics.validate()?;
+        Ok(metrics)
+    }
+
+    /// metric names must be unique as they become dashboard fields
+    fn validate(&self) -> Result<(), DbError> {
+        let mut names = HashSet::new();
+        for metric in &self.metrics {
+            if !names.insert(metric.name.as_str()) {
+                return Err(DbError::Generic(format!(
+                    "metric {} is defined more than once",
+                    metric.name
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Evaluate every metric for each game of `data` and store the results in its `custom`
+    /// values. A failing metric doesn't stop the others, its error is returned instead.
+    pub fn evaluate(&self, connection: &Connection, data: &mut AuroraData) -> Vec<DbError> {
+        let mut errors = vec![];
+
+        for metric in &self.metrics {
+            for agame in data.games.iter_mut() {
+                match metric.evaluate(connection, agame.game.game_id, agame.race_id) {
+                    Ok(values) => agame.custom.extend(values),
+                    Err(e) => errors.push(e),
+                }
+            }
+        }
+
+        errors
+    }
+}
+
+impl CustomMetric {
+    fn error<E: std::fmt::Display>(&self, e: E) -> DbError {
+        DbError::Generic(format!("metric {} failed: {}", self.name, e))
+    }
+
+    fn evaluate(
+        &self,
+        connection: &Connection,
+        game_id: u32,
+        race_id: u32,
+    ) -> Result<Vec<(String, f64)>, DbError> {
+        let mut stmt = connection.prepare(&self.sql).map_err(|e| self.error(e))?;
+
+        // only bind the parameters used by the query, unknown names are an error
+        let mut params: Vec<(&str, &dyn ToSql)> = vec![];
+        for (name, value) in [(":game_id", &game_id), (":race_id", &race_id)] {
+            if stmt
+                .parameter_index(name)
+                .map_err(|e| self.error(e))?
+                .is_some()
+            {
+                params.push((name, value));
+            }
+        }
+
+        let columns = stmt.column_count();
+        if columns == 0 || columns > 2 {
+            return Err(self.error("must return a number or key and number columns"));
+        }
+
+        let rows: Vec<_> = stmt
+            .query_map_named(&params, |row| {
+                if columns == 1 {
+                    (None, number(row, 0))
+                } else {
+                    (key(row), number(row, 1))
+                }
+            })
+            .and_then(|rows| rows.collect::<Result<Vec<_>, _>>())
+            .map_err(|e| self.error(e))?;
+
+        let mut values = vec![];
+        if columns == 1 {
+            if let Some((_, value)) = rows.into_iter().next() {
+                if let Some(value) = value.map_err(|e| self.error(e))? {
+                    values.push((self.name.clone(), value));
+                }
+            }
+        } else {
+            for (key, value) in rows {
+                if let (Some(key), Some(value)) = (key, value.map_err(|e| self.error(e))?) {
+                    values.push((format!("{}.{}", self.name, key), value));
+                }
+            }
+        }
+
+        Ok(values)
+    }
+}
+
+/// `NULL` is no value, e.g. a `sum` over no rows, anything else that isn't a number is an error
+fn number(row: &Row, index: usize) -> Result<Option<f64>, String> {
+    match row.get_checked(index) {
+        Ok(Value::Null) => Ok(None),
+        Ok(Value::Integer(i)) => Ok(Some(i as f64)),
+        Ok(Value::Real(f)) => Ok(Some(f)),
+        Ok(Value::Text(text)) => Err(format!("'{}' is not a number", text)),
+        Ok(Value::Blob(_)) => Err("a blob is not a number".to_string()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+fn key(row: &Row) -> Option<String> {
+    match row.get_checked(0) {
+        Ok(Value::Integer(i)) => Some(i.to_string()),
+        Ok(Value::Real(f)) => Some(f.to_string()),
+        Ok(Value::Text(text)) => Some(text),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::dashboard::test::game_data;
+
+    #[test]
+    fn metrics_should_store_numbers_and_key_rows() {
+        let connection = Connection::open_in_memory().unwrap();
+        connection
+            .execute_batch(
+                "create table FCT_Ship (GameID, RaceID, Class, Tonnage);
+                 insert into FCT_Ship values (0, 4, 'Scout', 1000);
+                 insert into FCT_Ship values (0, 4, 'Scout', 1000);
+                 insert into FCT_Ship values (0, 4, 'Tanker', 500);
+                 insert into FCT_Ship values (0, 5, 'Raider', 2000);",
+            )
+            .unwrap();
+
+        let metrics = MetricSet {
+            metrics: vec![
+                CustomMetric {
+                    name: "ships".to_string(),
+                    sql: "select count(*) from FCT_Ship where GameID = :game_id and RaceID = :race_id"
+                        .to_string(),
+                },
+                CustomMetric {
+                    name: "tonnage".to_string(),
+                    sql: "select Class, sum(Tonnage) from FCT_Ship where RaceID = :race_id group by Class"
+                        .to_string(),
+                },
+                CustomMetric {
+                    name: "broken".to_string(),
+                    sql: "select * from FCT_Missing".to_string(),
+                },
+                CustomMetric {
+                    // only mentions the parameter in a string
+                    name: "label".to_string(),
+                    sql: "select length(':game_id')".to_string(),
+                },
+            ],
+        };
+
+        let mut data = AuroraData {
+            games: vec![game_data(1.0)],
+        };
+        let errors = metrics.evaluate(&connection, &mut data);
+        assert_eq!(errors.len(), 1);
+
+        let custom = &data.games[0].custom;
+        assert_eq!(custom.get("ships"), Some(&3.0));
+        assert_eq!(custom.get("tonnage.Scout"), Some(&2000.0));
+        assert_eq!(custom.get("tonnage.Tanker"), Some(&500.0));
+        assert_eq!(custom.get("label"), Some(&8.0));
+        assert_eq!(custom.len(), 4);
+    }
+
+    #[test]
+    fn metrics_should_report_values_that_are_not_numbers() {
+        let connection = Connection::open_in_memory().unwrap();
+        let metric = |name: &str, sql: &str| CustomMetric {
+            name: name.to_string(),
+            sql: sql.to_string(),
+        };
+        let metrics = MetricSet {
+            metrics: vec![
+                metric("text", "select 'abc'"),
+                metric("rows", "select 'scout', 'abc'"),
+                metric("empty", "select sum(1) where 0"),
+            ],
+        };
+
+        let mut data = AuroraData {
+            games: vec![game_data(1.0)],
+        };
+        let errors = metrics.evaluate(&connection, &mut data);
+        assert_eq!(errors.len(), 2);
+        for DbError::Generic(msg) in errors {
+            assert!(msg.contains("'abc' is not a number"), "{}", msg);
+        }
+        assert!(data.games[0].custom.is_empty());
+    }
+
+    #[test]
+    fn metrics_should_have_unique_names() {
+        let metric = CustomMetric {
+            name: "ships".to_string(),
+            sql: "select 1".to_string(),
+        };
+        let metrics = MetricSet {
+            metrics: vec![metric.clone(), metric],
+        };
+
+        assert!(metrics.validate().is_err());
+    }
+}
@@ -0,0 +1,580 @@
+use crate::aurora_db::AuroraData;
+use crate::dump::DumpReader;
+use crate::field_map::{FieldMap, Scope};
+use parquet::basic::{Compression, LogicalType, Repetition, Type as PhysicalType};
+use parquet::data_type::{ByteArray, ByteArrayType, DoubleType, Int32Type};
+use parquet::file::properties::WriterProperties;
+use parquet::file::writer::SerializedFileWriter;
+use parquet::schema::types::Type;
+use std::fs::File;
+use std::path::Path;
+use std::sync::Arc;
+
+/// rows buffered before a row group is written
+const ROW_GROUP_SIZE: usize = 64 * 1024;
+
+#[derive(Debug)]
+pub enum ParquetError {
+    Generic(String),
+}
+
+impl From<parquet::errors::ParquetError> for ParquetError {
+    fn from(e: parquet::errors::ParquetError) -> Self {
+        ParquetError::Generic(format!("parquet error {}", e))
+    }
+}
+
+impl From<std::io::Error> for ParquetError {
+    fn from(e: std::io::Error) -> Self {
+        ParquetError::Generic(format!("io error {}", e))
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum ColumnKind {
+    Int,
+    Double,
+    Text,
+}
+
+#[derive(Debug, Clone)]
+pub enum Value {
+    Int(Option<i32>),
+    Double(Option<f64>),
+    Text(String),
+}
+
+struct Column {
+    name: String,
+    kind: ColumnKind,
+    optional: bool,
+    ints: Vec<i32>,
+    doubles: Vec<f64>,
+    texts: Vec<ByteArray>,
+    /// definition levels, only used by optional columns
+    levels: Vec<i16>,
+}
+
+/// A parquet file written one row at a time, rows are buffered into row groups
+pub struct TableWriter {
+    path: String,
+    writer: SerializedFileWriter<File>,
+    columns: Vec<Column>,
+    rows: usize,
+    total_rows: usize,
+}
+
+impl TableWriter {
+    fn create(path: &Path, columns: &[(&str, ColumnKind, bool)]) -> Result<Self, ParquetError> {
+        let fields = columns
+            .iter()
+            .map(|(name, kind, optional)| {
+                let (physical, logical) = match kind {
+                    ColumnKind::Int => (PhysicalType::INT32, None),
+                    ColumnKind::Double => (PhysicalType::DOUBLE, None),
+                    ColumnKind::Text => (PhysicalType::BYTE_ARRAY, Some(LogicalType::String)),
+                };
+                let repetition = if *optional {
+                    Repetition::OPTIONAL
+                } else {
+                    Repetition::REQUIRED
+                };
+
+                Type::primitive_type_builder(name, physical)
+                    .with_repetition(repetition)
+                    .with_logical_type(logical)
+                    .build()
+                    .map(Arc::new)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let schema = Type::group_type_builder("schema")
+            .with_fields(fields)
+            .build()?;
+
+        let properties = WriterProperties::builder()
+            .set_compression(Compression::SNAPPY)
+            .build();
+
+        let file = File::create(path)?;
+        let writer = SerializedFileWriter::new(file, Arc::new(schema), Arc::new(properties))?;
+
+        Ok(TableWriter {
+            path: path.to_string_lossy().to_string(),
+            writer,
+            columns: columns
+                .iter()
+                .map(|(name, kind, optional)| Column {
+                    name: name.to_string(),
+                    kind: *kind,
+                    optional: *optional,
+                    ints: vec![],
+                    doubles: vec![],
+                    texts: vec![],
+                    levels: vec![],
+                })
+                .collect(),
+            rows: 0,
+            total_rows: 0,
+        })
+    }
+
+    fn push(&mut self, row: Vec<Value>) -> Result<(), ParquetError> {
+        if row.len() != self.columns.len() {
+            return Err(ParquetError::Generic(format!(
+                "invalid row of {} values for {} columns in {}",
+                row.len(),
+                self.columns.len(),
+                self.path
+            )));
+        }
+
+        for (column, value) in self.columns.iter_mut().zip(row) {
+            let present = match (column.kind, value) {
+                (ColumnKind::Int, Value::Int(Some(v))) => {
+                    column.ints.push(v);
+                    true
+                }
+                (ColumnKind::Double, Value::Double(Some(v))) => {
+                    column.doubles.push(v);
+                    true
+                }
+                (ColumnKind::Text, Value::Text(v)) => {
+                    column.texts.push(ByteArray::from(v.as_str()));
+                    true
+                }
+                (_, Value::Int(None)) | (_, Value::Double(None)) if column.optional => false,
+                (kind, value) => {
+                    return Err(ParquetError::Generic(format!(
+                        "invalid value {:?} for column {} of kind {:?}",
+                        value, column.name, kind
+                    )))
+                }
+            };
+
+            if column.optional {
+                column.levels.push(if present { 1 } else { 0 });
+            }
+        }
+
+        self.rows += 1;
+        if self.rows >= ROW_GROUP_SIZE {
+            self.flush()?;
+        }
+
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), ParquetError> {
+        if self.rows == 0 {
+            return Ok(());
+        }
+
+        let mut row_group = self.writer.next_row_group()?;
+        for column in self.columns.iter_mut() {
+            let mut writer = row_group.next_column()?.ok_or_else(|| {
+                ParquetError::Generic(format!("missing column writer for {}", column.name))
+            })?;
+
+            let levels = if column.optional {
+                Some(column.levels.as_slice())
+            } else {
+                None
+            };
+
+            match column.kind {
+                ColumnKind::Int => {
+                    writer
+                        .typed::<Int32Type>()
+                        .write_batch(&column.ints, levels, None)?;
+                }
+                ColumnKind::Double => {
+                    writer
+                        .typed::<DoubleType>()
+                        .write_batch(&column.doubles, levels, None)?;
+                }
+                ColumnKind::Text => {
+                    writer
+                        .typed::<ByteArrayType>()
+                        .write_batch(&column.texts, levels, None)?;
+                }
+            }
+            writer.close()?;
+
+            column.ints.clear();
+            column.doubles.clear();
+            column.texts.clear();
+            column.levels.clear();
+        }
+        row_group.close()?;
+
+        self.total_rows += self.rows;
+        self.rows = 0;
+        Ok(())
+    }
+
+    fn close(mut self) -> Result<usize, ParquetError> {
+        self.flush()?;
+        self.writer.close()?;
+        println!("{} rows written at {}", self.total_rows, self.path);
+        Ok(self.total_rows)
+    }
+}
+
+/// Field columns of the tables, the built-in fields first and then the fields read through the
+/// field map and the custom metrics found in the dump
+#[derive(Debug, Clone, PartialEq)]
+pub struct SnapshotFields {
+    pub game: Vec<String>,
+    pub population: Vec<String>,
+    /// custom metric names, written as `custom.<name>` columns of the games table
+    pub custom: Vec<String>,
+}
+
+impl SnapshotFields {
+    pub fn builtin() -> Self {
+        let builtin = FieldMap::builtin();
+        SnapshotFields {
+            game: builtin.names(Scope::Game),
+            population: builtin.names(Scope::Population),
+            custom: vec![],
+        }
+    }
+
+    /// add the fields and metrics of `data` not known yet
+    pub fn add(&mut self, data: &AuroraData) {
+        for agame in &data.games {
+            add_names(&mut self.game, agame.values.keys());
+            add_names(&mut self.custom, agame.custom.keys());
+            for pop in &agame.populations {
+                add_names(&mut self.population, pop.values.keys());
+            }
+        }
+    }
+}
+
+fn add_names<'a>(names: &mut Vec<String>, keys: impl Iterator<Item = &'a String>) {
+    for key in keys {
+        if !names.contains(key) {
+            names.push(key.clone());
+        }
+    }
+}
+
+/// Tables written by `export_dump`, every row is stamped with the snapshot game time. The field
+/// columns are optional as older databases don't have all of them.
+pub struct SnapshotTables {
+    games: TableWriter,
+    populations: TableWriter,
+    mineral_deposits: TableWriter,
+    fields: SnapshotFields,
+}
+
+impl SnapshotTables {
+    pub fn create(output_dir: &Path, fields: SnapshotFields) -> Result<Self, ParquetError> {
+        use ColumnKind::*;
+
+        std::fs::create_dir_all(output_dir)?;
+
+        let custom_columns: Vec<String> = fields
+            .custom
+            .iter()
+            .map(|name| format!("custom.{}", name))
+            .collect();
+
+        let mut columns = vec![
+            ("game_time", Double, false),
+            ("date", Text, false),
+            ("game_id", Int, false),
+            ("game_name", Text, false),
+            ("start_year", Int, false),
+            ("race_id", Int, false),
+        ];
+        columns.extend(fields.game.iter().map(|name| (name.as_str(), Double, true)));
+        columns.extend(
+            custom_columns
+                .iter()
+                .map(|name| (name.as_str(), Double, true)),
+        );
+        let games = TableWriter::create(&output_dir.join("games.parquet"), &columns)?;
+
+        let mut columns = vec![
+            ("game_time", Double, false),
+            ("date", Text, false),
+            ("game_id", Int, false),
+            ("population_id", Int, false),
+            ("pop_name", Text, false),
+            ("system_id", Int, true),
+            ("system_body_id", Int, true),
+        ];
+        columns.extend(
+            fields
+                .population
+                .iter()
+                .map(|name| (name.as_str(), Double, true)),
+        );
+        let populations = TableWriter::create(&output_dir.join("populations.parquet"), &columns)?;
+
+        let mineral_deposits = TableWriter::create(
+            &output_dir.join("mineral_deposits.parquet"),
+            &[
+                ("game_time", Double, false),
+                ("date", Text, false),
+                ("game_id", Int, false),
+                ("population_id", Int, false),
+                ("material_id", Int, false),
+                ("amount", Double, false),
+                ("accessibility", Double, false),
+            ],
+        )?;
+
+        Ok(SnapshotTables {
+            games,
+            populations,
+            mineral_deposits,
+            fields,
+        })
+    }
+
+    pub fn append(&mut self, data: &AuroraData) -> Result<(), ParquetError> {
+        use Value::*;
+
+        fn int(value: u32) -> Value {
+            Int(Some(value as i32))
+        }
+
+        for agame in &data.games {
+            let game_time = Double(Some(agame.game.game_time));
+            let date = Text(agame.game.date().to_iso_datetime());
+            let game_id = int(agame.game.game_id);
+
+            let mut row = vec![
+                game_time.clone(),
+                date.clone(),
+                game_id.clone(),
+                Text(agame.game.game_name.clone()),
+                int(agame.game.start_year),
+                int(agame.race_id),
+            ];
+            row.extend(
+                self.fields
+                    .game
+                    .iter()
+                    .map(|name| Double(agame.values.get(name).copied())),
+            );
+            row.extend(
+                self.fields
+                    .custom
+                    .iter()
+                    .map(|name| Double(agame.custom.get(name).copied())),
+            );
+            self.games.push(row)?;
+
+            for pop in &agame.populations {
+                let mut row = vec![
+                    game_time.clone(),
+                    date.clone(),
+                    game_id.clone(),
+                    int(pop.population_id),
+                    Text(pop.pop_name.clone()),
+                    Int(pop.system_id.map(|id| id as i32)),
+                    Int(pop.system_body_id.map(|id| id as i32)),
+                ];
+                row.extend(
+                    self.fields
+                        .population
+                        .iter()
+                        .map(|name| Double(pop.values.get(name).copied())),
+                );
+                self.populations.push(row)?;
+
+                for deposit in pop.minerals.iter().flatten() {
+                    self.mineral_deposits.push(vec![
+                        game_time.clone(),
+                        date.clone(),
+                        game_id.clone(),
+                        int(pop.population_id),
+                        int(deposit.material_id),
+                        Double(Some(deposit.amount)),
+                        Double(Some(deposit.acc)),
+                    ])?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn close(self) -> Result<(), ParquetError> {
+        self.games.close()?;
+        self.populations.close()?;
+        self.mineral_deposits.close()?;
+        Ok(())
+    }
+}
+
+/// Convert every snapshot of the dump into `games.parquet`, `populations.parquet` and
+/// `mineral_deposits.parquet` inside `output_dir`. A first pass collects the fields of every
+/// snapshot, so fields added through the field map and custom metrics get their own columns.
+pub fn export_dump(dump_path: &str, output_dir: &Path) -> Result<(), ParquetError> {
+    let open =
+        || DumpReader::open(dump_path).map_err(|e| ParquetError::Generic(format!("{:?}", e)));
+    let read = |data| ParquetError::Generic(format!("{:?}", data));
+
+    let mut fields = SnapshotFields::builtin();
+    for data in open()? {
+        fields.add(&data.map_err(read)?);
+    }
+
+    let mut tables = SnapshotTables::create(output_dir, fields)?;
+    for data in open()? {
+        tables.append(&data.map_err(read)?)?;
+    }
+
+    tables.close()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::dashboard::test::game_data;
+    use crate::dump;
+    use crate::dump::test::remove_dump;
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+    use parquet::record::RowAccessor;
+
+    fn read_rows(path: &Path) -> Vec<parquet::record::Row> {
+        let reader = SerializedFileReader::new(File::open(path).unwrap()).unwrap();
+        reader
+            .get_row_iter(None)
+            .unwrap()
+            .map(|row| row.unwrap())
+            .collect()
+    }
+
+    /// value of the `name` column
+    fn double(row: &parquet::record::Row, name: &str) -> Option<f64> {
+        let index = row
+            .get_column_iter()
+            .position(|(column, _)| column == name)?;
+        row.get_double(index).ok()
+    }
+
+    #[test]
+    fn export_snapshots_as_parquet() {
+        let output_dir = std::env::temp_dir().join(format!(
+            "aurora_analytics_parquet_test_{}",
+            std::process::id()
+        ));
+
+        let mut old = game_data(2.0);
+        old.values.remove("wealth");
+
+        let mut tables = SnapshotTables::create(&output_dir, SnapshotFields::builtin()).unwrap();
+        tables
+            .append(&AuroraData {
+                games: vec![game_data(1.0)],
+            })
+            .unwrap();
+        tables.append(&AuroraData { games: vec![old] }).unwrap();
+        tables.close().unwrap();
+
+        let games = read_rows(&output_dir.join("games.parquet"));
+        assert_eq!(games.len(), 2);
+        assert_eq!(games[0].get_double(0).unwrap(), 1.0);
+        assert_eq!(games[0].get_string(3).unwrap(), "Game 01");
+        assert_eq!(double(&games[0], "wealth"), Some(40.0));
+        assert_eq!(double(&games[1], "wealth"), None);
+        assert_eq!(double(&games[1], "annual_wealth"), Some(10.0));
+
+        let populations = read_rows(&output_dir.join("populations.parquet"));
+        assert_eq!(populations.len(), 2);
+        assert_eq!(populations[0].get_string(4).unwrap(), "Pop 1");
+        assert_eq!(double(&populations[0], "population"), Some(3.0));
+        assert_eq!(double(&populations[0], "uridium"), None);
+
+        let deposits = read_rows(&output_dir.join("mineral_deposits.parquet"));
+        assert_eq!(deposits.len(), 4);
+        assert_eq!(deposits[1].get_int(4).unwrap(), 2);
+        assert_eq!(deposits[1].get_double(5).unwrap(), 430.0);
+
+        std::fs::remove_dir_all(&output_dir).unwrap();
+    }
+
+    #[test]
+    fn export_dump_should_write_field_map_fields_and_custom_metrics() {
+        let dump_path = std::env::temp_dir()
+            .join(format!(
+                "aurora_analytics_parquet_dump_{}.json",
+                std::process::id()
+            ))
+            .to_string_lossy()
+            .to_string();
+        let output_dir = std::env::temp_dir().join(format!(
+            "aurora_analytics_parquet_fields_{}",
+            std::process::id()
+        ));
+
+        dump::append(
+            &dump_path,
+            &AuroraData {
+                games: vec![game_data(1.0)],
+            },
+            5,
+        )
+        .unwrap();
+        let mut tracked = game_data(2.0);
+        tracked.values.insert("fighters".to_string(), 12.0);
+        tracked.custom.insert("ships".to_string(), 3.0);
+        tracked.populations[0]
+            .values
+            .insert("orbital_habitats".to_string(), 2.0);
+        dump::append(
+            &dump_path,
+            &AuroraData {
+                games: vec![tracked],
+            },
+            5,
+        )
+        .unwrap();
+
+        export_dump(&dump_path, &output_dir).unwrap();
+
+        let games = read_rows(&output_dir.join("games.parquet"));
+        assert_eq!(double(&games[0], "fighters"), None);
+        assert_eq!(double(&games[1], "fighters"), Some(12.0));
+        assert_eq!(double(&games[1], "custom.ships"), Some(3.0));
+        assert_eq!(double(&games[1], "wealth"), Some(40.0));
+
+        let populations = read_rows(&output_dir.join("populations.parquet"));
+        assert_eq!(double(&populations[0], "orbital_habitats"), None);
+        assert_eq!(double(&populations[1], "orbital_habitats"), Some(2.0));
+
+        remove_dump(&dump_path);
+        std::fs::remove_dir_all(&output_dir).unwrap();
+    }
+
+    #[test]
+    fn parquet_table_should_reject_rows_of_wrong_size() {
+        let path = std::env::temp_dir().join(format!(
+            "aurora_analytics_parquet_row_{}.parquet",
+            std::process::id()
+        ));
+
+        let mut table = TableWriter::create(
+            &path,
+            &[
+                ("a", ColumnKind::Int, false),
+                ("b", ColumnKind::Double, true),
+            ],
+        )
+        .unwrap();
+        assert!(table.push(vec![Value::Int(Some(1))]).is_err());
+        assert!(table
+            .push(vec![Value::Int(Some(1)), Value::Double(None)])
+            .is_ok());
+
+        drop(table);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,428 @@
+use crate::anomalies::AnomalyCfg;
+use crate::dashboard::{Dashboard, DashboardField, GameDashboard, HistoricalValue};
+use crate::growth;
+use std::fmt::Write;
+
+/// colonies drawn and listed in the report, by last population
+const TOP_COLONIES: usize = 5;
+const CHART_WIDTH: f64 = 800.0;
+const CHART_HEIGHT: f64 = 260.0;
+/// same margins and colors as the dashboard charts in `index.html`
+const MARGIN: (f64, f64, f64, f64) = (30.0, 20.0, 40.0, 70.0);
+const COLORS: [&str; 7] = [
+    "#F08080", "#4682B4", "#3CB371", "#DAA520", "#9370DB", "#FF8C00", "#20B2AA",
+];
+
+const STYLE: &str = "body { font-family: sans-serif; max-width: 860px; margin: auto; }
+table { border-collapse: collapse; margin-bottom: 20px; }
+th, td { border-bottom: 1px solid #ddd; padding: 4px 10px; text-align: right; }
+th:first-child, td:first-child { text-align: left; }
+.chart { margin-bottom: 20px; }
+.flag { color: #c0392b; }";
+
+/// escape text for HTML content and attributes
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// short number, e.g. `1.2M`, as the dashboard charts
+fn format_number(value: f64) -> String {
+    let abs = value.abs();
+    if abs >= 1e9 {
+        format!("{:.1}B", value / 1e9)
+    } else if abs >= 1e6 {
+        format!("{:.1}M", value / 1e6)
+    } else if abs >= 1e3 {
+        format!("{:.1}K", value / 1e3)
+    } else if abs >= 10.0 || value == 0.0 {
+        format!("{:.0}", value)
+    } else {
+        format!("{:.2}", value)
+    }
+}
+
+/// Line chart as inline SVG, one polyline per named series
+fn svg_chart(game: &GameDashboard, title: &str, series: &[(&str, &[HistoricalValue])]) -> String {
+    let (top, right, bottom, left) = MARGIN;
+    let plot_width = CHART_WIDTH - left - right;
+    let plot_height = CHART_HEIGHT - top - bottom;
+
+    let values = series.iter().flat_map(|(_, values)| values.iter());
+    let (mut min_x, mut max_x, mut max_y) = (f64::INFINITY, f64::NEG_INFINITY, 0.0_f64);
+    let mut min_y = 0.0_f64;
+    for value in values {
+        min_x = min_x.min(value.x);
+        max_x = max_x.max(value.x);
+        min_y = min_y.min(value.y);
+        max_y = max_y.max(value.y);
+    }
+    if max_y == min_y {
+        max_y = min_y + 1.0;
+    }
+
+    let sx = |x: f64| {
+        if max_x > min_x {
+            left + (x - min_x) / (max_x - min_x) * plot_width
+        } else {
+            left + plot_width / 2.0
+        }
+    };
+    let sy = |y: f64| top + plot_height - (y - min_y) / (max_y - min_y) * plot_height;
+
+    let mut svg = String::new();
+    let _ = write!(
+        svg,
+        r##"<svg xmlns="http://www.w3.org/2000/svg" width="{w}" height="{h}" viewBox="0 0 {w} {h}" font-size="11">"##,
+        w = CHART_WIDTH,
+        h = CHART_HEIGHT
+    );
+    let _ = write!(
+        svg,
+        r##"<text x="{}" y="18" text-anchor="middle" font-weight="bold" font-size="14">{}</text>"##,
+        CHART_WIDTH / 2.0,
+        escape(title)
+    );
+
+    if min_x > max_x {
+        let _ = write!(
+            svg,
+            r##"<text x="{}" y="{}" text-anchor="middle">no data</text></svg>"##,
+            CHART_WIDTH / 2.0,
+            CHART_HEIGHT / 2.0
+        );
+        return svg;
+    }
+
+    // y axis with the bounds and the middle value
+    for y in [min_y, (min_y + max_y) / 2.0, max_y] {
+        let _ = write!(
+            svg,
+            r##"<line x1="{x1}" x2="{x2}" y1="{y:.1}" y2="{y:.1}" stroke="#e5e5e5"/><text x="{tx}" y="{ty:.1}" text-anchor="end" fill="#555">{label}</text>"##,
+            x1 = left,
+            x2 = left + plot_width,
+            y = sy(y),
+            tx = left - 6.0,
+            ty = sy(y) + 4.0,
+            label = format_number(y)
+        );
+    }
+
+    // x axis with the first and last dates
+    let axis_y = top + plot_height;
+    let _ = write!(
+        svg,
+        r##"<line x1="{l}" x2="{r}" y1="{y}" y2="{y}" stroke="#555"/><line x1="{l}" x2="{l}" y1="{t}" y2="{y}" stroke="#555"/>"##,
+        l = left,
+        r = left + plot_width,
+        y = axis_y,
+        t = top
+    );
+    for (x, anchor) in [(min_x, "start"), (max_x, "end")] {
+        let _ = write!(
+            svg,
+            r##"<text x="{:.1}" y="{}" text-anchor="{}" fill="#555">{}</text>"##,
+            sx(x),
+            axis_y + 18.0,
+            anchor,
+            game.date(x)
+        );
+    }
+
+    for (index, (name, values)) in series.iter().enumerate() {
+        let color = COLORS[index % COLORS.len()];
+        let points: Vec<String> = values
+            .iter()
+            .map(|v| format!("{:.1},{:.1}", sx(v.x), sy(v.y)))
+            .collect();
+        let _ = write!(
+            svg,
+            r##"<polyline points="{}" fill="none" stroke="{}" stroke-width="2"/>"##,
+            points.join(" "),
+            color
+        );
+
+        if series.len() > 1 {
+            let x = left + index as f64 * 140.0;
+            let y = CHART_HEIGHT - 6.0;
+            let _ = write!(
+                svg,
+                r##"<rect x="{}" y="{}" width="10" height="10" fill="{}"/><text x="{}" y="{}">{}</text>"##,
+                x,
+                y - 9.0,
+                color,
+                x + 14.0,
+                y,
+                escape(name)
+            );
+        }
+    }
+
+    svg.push_str("</svg>");
+    svg
+}
+
+/// table of the first and last value of every field
+fn summary_table(html: &mut String, game: &GameDashboard, fields: &[DashboardField]) {
+    html.push_str("<table><tr><th>field</th><th>first</th><th>last</th><th>change</th></tr>");
+    for field in fields {
+        let (first, last) = match (field.historical.first(), field.historical.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => continue,
+        };
+        let _ = write!(
+            html,
+            "<tr><td>{}</td><td title=\"{}\">{}</td><td title=\"{}\">{}</td><td>{}</td></tr>",
+            escape(&field.name),
+            game.date(first.x),
+            format_number(first.y),
+            game.date(last.x),
+            format_number(last.y),
+            format_number(last.y - first.y)
+        );
+    }
+    html.push_str("</table>");
+}
+
+fn last_value(field: Option<&DashboardField>) -> f64 {
+    field
+        .and_then(|f| f.historical.last())
+        .map(|v| v.y)
+        .unwrap_or_default()
+}
+
+fn render_game(html: &mut String, game: &GameDashboard, anomalies: &AnomalyCfg) {
+    let times: Vec<f64> = game
+        .fields
+        .iter()
+        .chain(game.populations.iter().flat_map(|pop| pop.fields.iter()))
+        .flat_map(|f| f.historical.iter().map(|v| v.x))
+        .collect();
+    let first = times.iter().cloned().fold(f64::INFINITY, f64::min);
+    let last = times.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+
+    let _ = write!(html, "<h1>{}</h1>", escape(&game.game_name));
+    if first <= last {
+        let _ = write!(
+            html,
+            "<p>Game started in {}, from {} to {}, {} colonies.</p>",
+            game.start_year,
+            game.date(first),
+            game.date(last),
+            game.populations.len()
+        );
+    }
+
+    // summary
+    let totals = game.totals();
+    html.push_str("<h2>Empire</h2>");
+    summary_table(html, game, &game.fields);
+    summary_table(html, game, &totals);
+    if !game.custom.is_empty() {
+        html.push_str("<h3>Custom metrics</h3>");
+        summary_table(html, game, &game.custom);
+    }
+
+    for field in game.fields.iter().chain(totals.iter()) {
+        if field.historical.iter().all(|v| v.y <= 0.0) {
+            continue;
+        }
+        let _ = write!(
+            html,
+            "<div class=\"chart\">{}</div>",
+            svg_chart(game, &field.name, &[(&field.name, &field.historical)])
+        );
+    }
+
+    // top colonies
+    let mut colonies: Vec<_> = game
+        .populations
+        .iter()
+        .map(|pop| {
+            let population = pop.fields.iter().find(|f| f.name == "population");
+            (pop, population, last_value(population))
+        })
+        .filter(|(_, population, _)| population.is_some())
+        .collect();
+    colonies.sort_by(|a, b| b.2.total_cmp(&a.2));
+    colonies.truncate(TOP_COLONIES);
+
+    if !colonies.is_empty() {
+        let projections = growth::project_game(game, growth::DEFAULT_YEARS);
+        let _ = write!(html, "<h2>Top {} colonies</h2>", colonies.len());
+        let _ = write!(
+            html,
+            "<table><tr><th>colony</th><th>population</th><th>growth rate</th>\
+             <th>capacity</th><th>in {} years</th></tr>",
+            growth::DEFAULT_YEARS
+        );
+        for (pop, _, population) in &colonies {
+            let projection = projections
+                .iter()
+                .find(|p| p.population_id == pop.population_id);
+            let rate = projection
+                .map(|p| format!("{:.3}", p.model.rate))
+                .unwrap_or_default();
+            let capacity = projection
+                .and_then(|p| p.model.capacity)
+                .map(format_number)
+                .unwrap_or_default();
+            let flag = if projection.is_some_and(|p| p.near_capacity) {
+                " <span class=\"flag\">near capacity</span>"
+            } else {
+                ""
+            };
+            let projected = projection
+                .and_then(|p| p.projected.last())
+                .map(|v| format_number(v.y))
+                .unwrap_or_default();
+            let _ = write!(
+                html,
+                "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}{}</td><td>{}</td></tr>",
+                escape(&pop.population_name),
+                format_number(*population),
+                rate,
+                capacity,
+                flag,
+                projected
+            );
+        }
+        html.push_str("</table>");
+
+        let series: Vec<(&str, &[HistoricalValue])> = colonies
+            .iter()
+            .filter_map(|(pop, population, _)| {
+                population.map(|f| (pop.population_name.as_str(), f.historical.as_slice()))
+            })
+            .collect();
+        let _ = write!(
+            html,
+            "<div class=\"chart\">{}</div>",
+            svg_chart(game, "population", &series)
+        );
+    }
+
+    // events
+    let mut events: Vec<(f64, &str, String, String)> = vec![];
+    for alert in &game.alerts {
+        events.push((
+            alert.game_time,
+            "alert",
+            alert.population_name.clone().unwrap_or_default(),
+            format!(
+                "{} {} {} {}",
+                alert.field,
+                format_number(alert.value),
+                alert.condition,
+                format_number(alert.threshold)
+            ),
+        ));
+    }
+    for anomaly in anomalies.detect(game) {
+        events.push((
+            anomaly.game_time,
+            "anomaly",
+            anomaly.population_name.clone().unwrap_or_default(),
+            format!(
+                "{} {} to {}",
+                anomaly.field,
+                format_number(anomaly.previous),
+                format_number(anomaly.value)
+            ),
+        ));
+    }
+    events.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    html.push_str("<h2>Events</h2>");
+    if events.is_empty() {
+        html.push_str("<p>No alerts or anomalies.</p>");
+    } else {
+        html.push_str("<table><tr><th>date</th><th>event</th><th>colony</th><th>details</th></tr>");
+        for (x, kind, population, details) in events {
+            let _ = write!(
+                html,
+                "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                game.date(x),
+                kind,
+                escape(&population),
+                escape(&details)
+            );
+        }
+        html.push_str("</table>");
+    }
+}
+
+/// Self-contained HTML report of `game_ids`, every game when empty, with summary tables, charts
+/// of the empire totals and top colonies and a timeline of the alerts and anomalies
+pub fn render(dashboard: &Dashboard, game_ids: &[u32], anomalies: &AnomalyCfg) -> String {
+    let games: Vec<&GameDashboard> = dashboard
+        .games
+        .iter()
+        .filter(|game| game_ids.is_empty() || game_ids.contains(&game.game_id))
+        .collect();
+
+    let title = match games.as_slice() {
+        [game] => game.game_name.as_str(),
+        _ => "Aurora 4x campaigns",
+    };
+
+    let mut html = String::new();
+    let _ = write!(
+        html,
+        "<!DOCTYPE HTML>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n<style>\n{}\n</style>\n</head>\n<body>\n",
+        escape(title),
+        STYLE
+    );
+    for game in games {
+        render_game(&mut html, game, anomalies);
+        html.push('\n');
+    }
+    html.push_str("</body>\n</html>\n");
+    html
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::alerts::AlertRule;
+    use crate::aurora_db::AuroraData;
+    use crate::calendar::SECONDS_PER_YEAR;
+    use crate::dashboard::test::game_data;
+
+    #[test]
+    fn report_should_render_tables_charts_and_events() {
+        let mut dashboard = Dashboard::new();
+        let mut first = game_data(0.0);
+        first.game.game_name = "Rise & <Fall>".to_string();
+        // every fixture value is positive, so each game field and total has a chart
+        let field_charts = first.values.len() + first.populations[0].values.len();
+        let mut second = game_data(SECONDS_PER_YEAR);
+        second.game.game_name = first.game.game_name.clone();
+        second.populations[0]
+            .values
+            .insert("fuel_stockpile".to_string(), 0.5);
+        dashboard.append(&AuroraData {
+            games: vec![first, second],
+        });
+        let rules: Vec<AlertRule> =
+            serde_json::from_str(r#"[{"field": "fuel_stockpile", "below": 1}]"#).unwrap();
+        dashboard.evaluate_alerts(&rules);
+
+        let html = render(&dashboard, &[], &AnomalyCfg::default());
+        assert!(html.starts_with("<!DOCTYPE HTML>"));
+        assert!(html.contains("<title>Rise &amp; &lt;Fall&gt;</title>"));
+        assert!(html.contains("<h2>Top 1 colonies</h2>"));
+        assert!(html.contains("<td>fuel_stockpile</td><td title=\"0002-01-01\">1.00</td><td title=\"0003-01-01\">0.50</td>"));
+        assert!(
+            html.contains("<td>0003-01-01</td><td>alert</td><td>Pop 1</td><td>fuel_stockpile 0.50 below 1.00</td>")
+        );
+        // one chart per non zero game field and total, and the top colonies
+        assert_eq!(html.matches("<svg").count(), field_charts + 1);
+        // no external resources
+        assert!(!html.contains("src="));
+
+        assert!(render(&dashboard, &[9], &AnomalyCfg::default()).contains("<body>\n</body>"));
+    }
+}
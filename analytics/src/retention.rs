@@ -0,0 +1,523 @@
+use crate::aurora_db::{AuroraData, AuroraGameData, FCTMineralDeposit, FCTPopulation};
+use crate::calendar::{GameDate, SECONDS_PER_YEAR};
+use crate::dashboard::{Dashboard, DashboardField, HistoricalValue};
+use crate::dump::{DumpError, DumpFormat, DumpLock, DumpReader, DumpWriter};
+use indexmap::IndexMap;
+use std::collections::{BTreeMap, HashMap};
+
+/// How much history is kept at full detail.
+///
+/// Values of the last `full_detail_years` game years of each game are kept untouched, older ones
+/// are reduced to one value per game calendar year.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetentionPolicy {
+    pub full_detail_years: f64,
+}
+
+/// Number of snapshots before and after compacting a dump
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CompactStats {
+    pub before: usize,
+    pub after: usize,
+}
+
+impl RetentionPolicy {
+    pub fn new(full_detail_years: u32) -> Self {
+        RetentionPolicy {
+            full_detail_years: full_detail_years as f64,
+        }
+    }
+
+    /// game time before which values are bucketed, relative to the last game time
+    fn cutoff(&self, last_game_time: f64) -> f64 {
+        last_game_time - self.full_detail_years * SECONDS_PER_YEAR
+    }
+
+    /// Replace the historical values older than the retention window by their yearly averages
+    pub fn apply_to_dashboard(&self, dashboard: &mut Dashboard) {
+        for game in &mut dashboard.games {
+            let last = game
+                .fields
+                .iter()
+                .chain(game.populations.iter().flat_map(|pop| pop.fields.iter()))
+                .filter_map(|field| field.historical.last())
+                .map(|value| value.x)
+                .fold(f64::NEG_INFINITY, f64::max);
+
+            if last == f64::NEG_INFINITY {
+                continue;
+            }
+
+            let cutoff = self.cutoff(last);
+            let start_year = game.start_year;
+            let fields = game.fields.iter_mut().chain(
+                game.populations
+                    .iter_mut()
+                    .flat_map(|pop| pop.fields.iter_mut()),
+            );
+
+            for field in fields {
+                compact_field(field, start_year, cutoff);
+            }
+        }
+    }
+
+    /// Rewrite the dump keeping every snapshot of the retention window and a single snapshot
+    /// with the average values of each game calendar year before it, so a dashboard rebuilt from
+    /// the dump has the same yearly averages. The dump is replaced only after the new one is
+    /// completely written. Snapshots appended by a running collector meanwhile are copied under
+    /// the dump lock, held until the dump is replaced so no append can slip in between.
+    pub fn apply_to_dump(
+        &self,
+        path: &str,
+        keyframe_interval: usize,
+    ) -> Result<CompactStats, DumpError> {
+        // first pass, last game time of every game
+        let mut last_time: HashMap<u32, f64> = HashMap::new();
+        let mut before = 0;
+
+        for data in DumpReader::open(path)? {
+            for agame in &data?.games {
+                let last = last_time
+                    .entry(agame.game.game_id)
+                    .or_insert(agame.game.game_time);
+                *last = last.max(agame.game.game_time);
+            }
+            before += 1;
+        }
+
+        let is_old = |agame: &AuroraGameData| {
+            last_time
+                .get(&agame.game.game_id)
+                .is_some_and(|last| agame.game.game_time < self.cutoff(*last))
+        };
+
+        // second pass, average the games of each year before the retention window
+        let mut years: BTreeMap<(u32, i32), YearAverage> = BTreeMap::new();
+        for data in DumpReader::open(path)? {
+            for agame in data?.games.iter().filter(|agame| is_old(agame)) {
+                years
+                    .entry((agame.game.game_id, agame.game.date().year))
+                    .or_default()
+                    .add(agame);
+            }
+        }
+        let mut averages: Vec<AuroraGameData> = years
+            .into_values()
+            .filter_map(YearAverage::finish)
+            .collect();
+        averages.sort_by(|a, b| a.game.game_time.total_cmp(&b.game.game_time));
+
+        // third pass, write the averages and the retention window into a temporary file
+        let tmp = TmpFile::new(format!("{}.tmp", path));
+        let mut writer =
+            DumpWriter::create_with_format(&tmp.path, DumpFormat::detect(path), keyframe_interval)?;
+        let mut after = 0;
+        let mut read = 0;
+
+        for agame in averages {
+            writer.write(&AuroraData { games: vec![agame] })?;
+            after += 1;
+        }
+
+        for data in DumpReader::open(path)? {
+            let mut data = data?;
+            read += 1;
+            if !data.games.is_empty() {
+                data.games.retain(|agame| !is_old(agame));
+                if data.games.is_empty() {
+                    continue;
+                }
+            }
+            writer.write(&data)?;
+            after += 1;
+        }
+
+        // the collector reopens the dump on each append, anything appended until now would be
+        // lost by the rename, and appends wait for the lock until the dump is replaced
+        let _lock = DumpLock::acquire(path)?;
+        for data in DumpReader::open(path)?.skip(read) {
+            writer.write(&data?)?;
+            before += 1;
+            after += 1;
+        }
+
+        drop(writer);
+        tmp.persist(path)
+            .map_err(|e| DumpError::Generic(format!("fail to write {}: {}", path, e)))?;
+
+        Ok(CompactStats { before, after })
+    }
+}
+
+/// File removed when dropped unless it was renamed by `persist`, so a failed compaction doesn't
+/// leave its temporary dump behind
+struct TmpFile {
+    path: String,
+    persisted: bool,
+}
+
+impl TmpFile {
+    fn new(path: String) -> Self {
+        TmpFile {
+            path,
+            persisted: false,
+        }
+    }
+
+    fn persist(mut self, to: &str) -> std::io::Result<()> {
+        std::fs::rename(&self.path, to)?;
+        self.persisted = true;
+        Ok(())
+    }
+}
+
+impl Drop for TmpFile {
+    fn drop(&mut self) {
+        if !self.persisted {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Mean {
+    sum: f64,
+    count: usize,
+}
+
+impl Mean {
+    fn add(&mut self, value: f64) {
+        self.sum += value;
+        self.count += 1;
+    }
+
+    fn value(&self) -> f64 {
+        self.sum / self.count as f64
+    }
+}
+
+type Means = IndexMap<String, Mean>;
+
+fn add_values(means: &mut Means, values: &IndexMap<String, f64>) {
+    for (name, value) in values {
+        means.entry(name.clone()).or_default().add(*value);
+    }
+}
+
+fn mean_values(means: Means) -> IndexMap<String, f64> {
+    means
+        .into_iter()
+        .map(|(name, mean)| (name, mean.value()))
+        .collect()
+}
+
+/// Games of a year before the retention window, written into the dump as a single game with the
+/// average game time and values. Names come from the game with the highest game time, as
+/// snapshots are not always appended in game time order, e.g. after a backfill.
+#[derive(Default)]
+struct YearAverage {
+    last: Option<AuroraGameData>,
+    game_time: Mean,
+    values: Means,
+    custom: Means,
+    populations: BTreeMap<u32, PopulationAverage>,
+}
+
+impl YearAverage {
+    fn add(&mut self, agame: &AuroraGameData) {
+        let game_time = agame.game.game_time;
+        self.game_time.add(game_time);
+        add_values(&mut self.values, &agame.values);
+        add_values(&mut self.custom, &agame.custom);
+        for pop in &agame.populations {
+            self.populations
+                .entry(pop.population_id)
+                .or_default()
+                .add(game_time, pop);
+        }
+
+        if self
+            .last
+            .as_ref()
+            .is_none_or(|last| game_time >= last.game.game_time)
+        {
+            self.last = Some(agame.clone());
+        }
+    }
+
+    fn finish(self) -> Option<AuroraGameData> {
+        let mut agame = self.last?;
+        agame.game.game_time = self.game_time.value();
+        agame.values = mean_values(self.values);
+        agame.custom = mean_values(self.custom);
+        agame.populations = self
+            .populations
+            .into_values()
+            .filter_map(PopulationAverage::finish)
+            .collect();
+        Some(agame)
+    }
+}
+
+#[derive(Default)]
+struct PopulationAverage {
+    last: Option<(f64, FCTPopulation)>,
+    values: Means,
+    /// amount and accessibility by material id
+    deposits: BTreeMap<u32, (Mean, Mean)>,
+}
+
+impl PopulationAverage {
+    fn add(&mut self, game_time: f64, pop: &FCTPopulation) {
+        add_values(&mut self.values, &pop.values);
+        for deposit in pop.minerals.iter().flatten() {
+            let (amount, acc) = self.deposits.entry(deposit.material_id).or_default();
+            amount.add(deposit.amount);
+            acc.add(deposit.acc);
+        }
+
+        if self
+            .last
+            .as_ref()
+            .is_none_or(|(last, _)| game_time >= *last)
+        {
+            self.last = Some((game_time, pop.clone()));
+        }
+    }
+
+    fn finish(self) -> Option<FCTPopulation> {
+        let (_, mut pop) = self.last?;
+        pop.values = mean_values(self.values);
+        if !self.deposits.is_empty() {
+            pop.minerals = Some(
+                self.deposits
+                    .into_iter()
+                    .map(|(material_id, (amount, acc))| FCTMineralDeposit {
+                        material_id,
+                        amount: amount.value(),
+                        acc: acc.value(),
+                    })
+                    .collect(),
+            );
+        }
+        Some(pop)
+    }
+}
+
+/// average the values before `cutoff` of each game year into a single value
+fn compact_field(field: &mut DashboardField, start_year: u32, cutoff: f64) {
+    let split = field.historical.partition_point(|value| value.x < cutoff);
+    if split == 0 {
+        return;
+    }
+
+    let mut compacted: Vec<HistoricalValue> = vec![];
+    let mut bucket: Vec<&HistoricalValue> = vec![];
+    let mut bucket_year = None;
+
+    let year = |value: &HistoricalValue| GameDate::from_game_time(start_year, value.x).year;
+
+    for value in &field.historical[..split] {
+        if bucket_year.is_some() && bucket_year != Some(year(value)) {
+            compacted.push(average(&bucket));
+            bucket.clear();
+        }
+        bucket_year = Some(year(value));
+        bucket.push(value);
+    }
+    compacted.push(average(&bucket));
+
+    compacted.extend_from_slice(&field.historical[split..]);
+    field.historical = compacted;
+}
+
+fn average(values: &[&HistoricalValue]) -> HistoricalValue {
+    let len = values.len() as f64;
+    HistoricalValue {
+        x: values.iter().map(|v| v.x).sum::<f64>() / len,
+        y: values.iter().map(|v| v.y).sum::<f64>() / len,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::dashboard::test::game_data;
+    use crate::dashboard::DashboardDb;
+    use crate::dump;
+    use crate::dump::test::remove_dump;
+
+    const MONTH: f64 = SECONDS_PER_YEAR / 12.0;
+
+    fn snapshot(time: f64) -> AuroraData {
+        AuroraData {
+            games: vec![game_data(time)],
+        }
+    }
+
+    #[test]
+    fn retention_should_average_old_values_by_year() {
+        let mut dashboard = Dashboard::new();
+        // 4 years of monthly snapshots
+        for i in 0..48 {
+            dashboard.append(&snapshot(i as f64 * MONTH + 1.0));
+        }
+
+        RetentionPolicy::new(2).apply_to_dashboard(&mut dashboard);
+
+        let game = &dashboard.games[0];
+        let wealth = &game.fields[0].historical;
+        // 2 yearly averages and the last 2 years (+1 value at the cutoff)
+        assert_eq!(wealth.len(), 2 + 25);
+        assert!(wealth.windows(2).all(|w| w[0].x < w[1].x));
+        assert_eq!(wealth[0].y, 40.0);
+
+        let population = &game.populations[0].get_field("population").historical;
+        assert_eq!(population.len(), wealth.len());
+
+        // applying the policy again doesn't change anything
+        let before = serde_json::to_string(&dashboard).unwrap();
+        RetentionPolicy::new(2).apply_to_dashboard(&mut dashboard);
+        assert_eq!(serde_json::to_string(&dashboard).unwrap(), before);
+    }
+
+    fn temp_dump(name: &str) -> String {
+        let path = std::env::temp_dir()
+            .join(format!("aurora_analytics_{}_{}", std::process::id(), name))
+            .to_string_lossy()
+            .to_string();
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    /// monthly snapshot with the month as population
+    fn monthly_snapshot(month: usize) -> AuroraData {
+        let mut data = snapshot(month as f64 * MONTH + 1.0);
+        data.games[0].populations[0]
+            .values
+            .insert("population".to_string(), month as f64);
+        data
+    }
+
+    #[test]
+    fn retention_should_average_old_years_in_dump() {
+        let path = temp_dump("compact.json");
+
+        let mut dashboard = Dashboard::new();
+        for month in 0..48 {
+            dump::append(&path, &monthly_snapshot(month), 5).unwrap();
+            dashboard.append(&monthly_snapshot(month));
+        }
+
+        let stats = RetentionPolicy::new(2).apply_to_dump(&path, 5).unwrap();
+        assert_eq!(
+            stats,
+            CompactStats {
+                before: 48,
+                after: 27
+            }
+        );
+
+        let snapshots: Vec<AuroraData> = DumpReader::open(&path)
+            .unwrap()
+            .map(|data| data.unwrap())
+            .collect();
+        let times: Vec<f64> = snapshots
+            .iter()
+            .map(|data| data.games[0].game.game_time)
+            .collect();
+        let population = |i: usize| snapshots[i].games[0].populations[0].values["population"];
+        assert_eq!(times.len(), 27);
+        // months 0 to 11 and 12 to 22, the rest is the retention window
+        assert!((times[0] - (5.5 * MONTH + 1.0)).abs() < 1e-6);
+        assert!((population(0) - 5.5).abs() < 1e-9);
+        assert!((times[1] - (17.0 * MONTH + 1.0)).abs() < 1e-6);
+        assert!((population(1) - 17.0).abs() < 1e-9);
+        assert_eq!(times[2], 23.0 * MONTH + 1.0);
+        assert_eq!(times[26], 47.0 * MONTH + 1.0);
+        let deposits = snapshots[0].games[0].populations[0].minerals.as_ref();
+        assert_eq!(deposits.unwrap()[1].amount, 430.0);
+
+        // the dashboard rebuilt from the dump has the averages of the compacted dashboard
+        RetentionPolicy::new(2).apply_to_dashboard(&mut dashboard);
+        let rebuilt = DashboardDb::load_aurora_dump(&path).unwrap();
+        let expected = &dashboard.games[0].populations[0].get_field("population");
+        let actual = &rebuilt.games[0].populations[0].get_field("population");
+        assert_eq!(actual.historical.len(), expected.historical.len());
+        for (a, e) in actual.historical.iter().zip(&expected.historical) {
+            assert!((a.x - e.x).abs() < 1e-6 && (a.y - e.y).abs() < 1e-9);
+        }
+
+        remove_dump(&path);
+    }
+
+    #[test]
+    fn retention_should_name_averages_after_highest_game_time() {
+        let path = temp_dump("compact_order.json");
+
+        // the end of the first year was imported before its middle
+        for month in [11, 5, 30, 36] {
+            let mut data = monthly_snapshot(month);
+            data.games[0].game.game_name = format!("Game at {}", month);
+            data.games[0].populations[0].pop_name = format!("Pop at {}", month);
+            dump::append(&path, &data, 5).unwrap();
+        }
+
+        RetentionPolicy::new(1).apply_to_dump(&path, 5).unwrap();
+
+        let games: Vec<AuroraGameData> = DumpReader::open(&path)
+            .unwrap()
+            .map(|data| data.unwrap().games.remove(0))
+            .collect();
+        assert_eq!(games.len(), 3);
+        assert_eq!(games[0].game.game_name, "Game at 11");
+        assert_eq!(games[0].populations[0].pop_name, "Pop at 11");
+        assert!((games[0].game.game_time - (8.0 * MONTH + 1.0)).abs() < 1e-6);
+        assert_eq!(games[1].game.game_time, 30.0 * MONTH + 1.0);
+
+        remove_dump(&path);
+    }
+
+    #[test]
+    fn retention_should_remove_temporary_dump_on_failure() {
+        let path = temp_dump("compact_failure.json");
+        for month in 0..48 {
+            dump::append(&path, &monthly_snapshot(month), 5).unwrap();
+        }
+
+        // the lock can't be taken once the temporary dump is written
+        let lock_path = format!("{}.lock", path);
+        std::fs::remove_file(&lock_path).unwrap();
+        std::fs::create_dir(&lock_path).unwrap();
+
+        assert!(RetentionPolicy::new(2).apply_to_dump(&path, 5).is_err());
+        assert!(!std::path::Path::new(&format!("{}.tmp", path)).exists());
+        assert_eq!(DumpReader::open(&path).unwrap().count(), 48);
+
+        std::fs::remove_dir(&lock_path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn retention_should_keep_appending_into_compacted_dump() {
+        let path = temp_dump("compact_append.json");
+
+        // a collector keeps its writer while the dump is compacted
+        let mut writer = DumpWriter::append(&path, 5).unwrap();
+        for i in 0..48 {
+            writer.write(&snapshot(i as f64 * MONTH + 1.0)).unwrap();
+        }
+        RetentionPolicy::new(2).apply_to_dump(&path, 5).unwrap();
+        writer.write(&snapshot(48.0 * MONTH + 1.0)).unwrap();
+
+        let times: Vec<f64> = DumpReader::open(&path)
+            .unwrap()
+            .map(|data| data.unwrap().games[0].game.game_time)
+            .collect();
+        assert_eq!(times.len(), 28);
+        assert_eq!(times[27], 48.0 * MONTH + 1.0);
+
+        remove_dump(&path);
+    }
+}
@@ -0,0 +1,1171 @@
+use crate::alerts::Alert;
+use crate::collector::{self, CollectorCfg, SnapshotEvent};
+use crate::compare::Comparison;
+use crate::config::Config;
+use crate::dashboard::{
+    Dashboard, DashboardDb, DashboardField, DashboardPopulation, GameDashboard, SeriesFilter,
+    SharedDashboard,
+};
+use crate::export::{self, ExportError, ExportTarget};
+use crate::growth;
+use rocket::figment::providers::Serialized;
+use rocket::http::{ContentType, Status};
+use rocket::request::FromParam;
+use rocket::response::stream::{Event, EventStream};
+use rocket::response::{content, status};
+use rocket::tokio::select;
+use rocket::tokio::sync::broadcast::{self, error::RecvError, Sender};
+use rocket::{
+    catch, catchers, get, routes, Build, FromForm, Request, Responder, Rocket, Shutdown, State,
+};
+use serde::Serialize;
+use std::path::Path;
+use std::sync::{Arc, Mutex, RwLock, RwLockReadGuard};
+use std::time::SystemTime;
+
+/// Body of every error response
+#[derive(Serialize, Debug)]
+pub struct ErrorDto<'a> {
+    /// stable identifier of the error, e.g. `game_not_found`
+    code: &'a str,
+    error: String,
+}
+
+fn error_body(code: &str, error: String) -> String {
+    serde_json::to_string(&ErrorDto { code, error })
+        .unwrap_or_else(|_| format!("{{\"code\":\"{}\"}}", code))
+}
+
+#[derive(Responder, Debug)]
+#[response(status = 400, content_type = "json")]
+pub struct Generic400 {
+    body: String,
+}
+
+#[derive(Responder, Debug)]
+#[response(status = 404, content_type = "json")]
+pub struct Generic404 {
+    body: String,
+}
+
+#[derive(Responder, Debug)]
+#[response(status = 500, content_type = "json")]
+pub struct Generic500 {
+    body: String,
+}
+
+#[derive(Responder, Debug)]
+pub enum ApiError {
+    BadRequest(Generic400),
+    NotFound(Generic404),
+    Internal(Generic500),
+}
+
+impl ApiError {
+    pub fn bad_request(code: &str, error: String) -> Self {
+        ApiError::BadRequest(Generic400 {
+            body: error_body(code, error),
+        })
+    }
+
+    pub fn not_found(code: &str, error: String) -> Self {
+        ApiError::NotFound(Generic404 {
+            body: error_body(code, error),
+        })
+    }
+
+    pub fn internal(code: &str, error: String) -> Self {
+        ApiError::Internal(Generic500 {
+            body: error_body(code, error),
+        })
+    }
+
+    fn game_not_found(game_id: u32) -> Self {
+        ApiError::not_found("game_not_found", format!("game {} not found", game_id))
+    }
+
+    fn population_not_found(population_id: u32) -> Self {
+        ApiError::not_found(
+            "population_not_found",
+            format!("population {} not found", population_id),
+        )
+    }
+}
+
+impl From<std::io::Error> for ApiError {
+    fn from(e: std::io::Error) -> Self {
+        ApiError::internal("io_error", format!("io error {:?}", e))
+    }
+}
+
+impl From<serde_json::Error> for ApiError {
+    fn from(e: serde_json::Error) -> Self {
+        ApiError::internal("parser_error", format!("parser error {:?}", e))
+    }
+}
+
+#[catch(404)]
+fn not_found(req: &Request) -> Generic404 {
+    Generic404 {
+        body: error_body("not_found", format!("{} not found", req.uri())),
+    }
+}
+
+#[catch(422)]
+fn unprocessable(req: &Request) -> Generic400 {
+    Generic400 {
+        body: error_body(
+            "bad_request",
+            format!("invalid parameters for {}", req.uri()),
+        ),
+    }
+}
+
+#[catch(default)]
+fn default_catcher(status: Status, req: &Request) -> status::Custom<content::Json<String>> {
+    status::Custom(
+        status,
+        content::Json(error_body(
+            &status_code(status),
+            format!("{} failed with status {}", req.uri(), status.code),
+        )),
+    )
+}
+
+/// error code of a status, e.g. `method_not_allowed` for 405
+fn status_code(status: Status) -> String {
+    match status.reason() {
+        Some(reason) => reason
+            .to_lowercase()
+            .replace('\'', "")
+            .replace(|c: char| !c.is_ascii_alphanumeric(), "_"),
+        None => format!("status_{}", status.code),
+    }
+}
+
+/// web assets embedded into the binary so the dashboard works offline and from any directory
+const ASSETS: &[(&str, &str)] = &[
+    ("index.html", include_str!("../resources/index.html")),
+    ("d3.v7.min.js", include_str!("../resources/d3.v7.min.js")),
+];
+
+fn asset(name: &str) -> Option<(ContentType, &'static str)> {
+    let (_, body) = ASSETS.iter().find(|(asset, _)| *asset == name)?;
+    let content_type = Path::new(name)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .and_then(ContentType::from_extension)
+        .unwrap_or(ContentType::Plain);
+    Some((content_type, body))
+}
+
+#[get("/")]
+fn index() -> Result<(ContentType, &'static str), ApiError> {
+    asset("index.html").ok_or_else(|| asset_not_found("index.html"))
+}
+
+#[get("/assets/<name>")]
+fn assets(name: &str) -> Result<(ContentType, &'static str), ApiError> {
+    asset(name).ok_or_else(|| asset_not_found(name))
+}
+
+fn asset_not_found(name: &str) -> ApiError {
+    ApiError::not_found("asset_not_found", format!("asset {} not found", name))
+}
+
+#[get("/data")]
+fn data(dashboard: &State<ServedDashboard>) -> Result<content::Json<String>, ApiError> {
+    let dashboard = get_data(dashboard)?;
+    let body = serde_json::to_string(&*dashboard)?;
+    Ok(content::Json(body))
+}
+
+fn get_data(dashboard: &ServedDashboard) -> Result<RwLockReadGuard<'_, Dashboard>, ApiError> {
+    dashboard.reload()?;
+    dashboard
+        .dashboard
+        .read()
+        .map_err(|_| ApiError::internal("lock_poisoned", "dashboard lock poisoned".to_string()))
+}
+
+#[derive(Serialize, Debug)]
+pub struct KeyValudDto<'a> {
+    id: u32,
+    name: &'a str,
+}
+
+#[derive(Serialize, Debug)]
+pub struct HistoricalValueDto {
+    x: f64,
+    y: f64,
+    /// game calendar date in `YYYY-MM-DDTHH:MM:SS` format
+    date: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct FieldDto<'a> {
+    name: &'a str,
+    historical: Vec<HistoricalValueDto>,
+}
+
+impl<'a> FieldDto<'a> {
+    fn new(game: &GameDashboard, field: &'a DashboardField, filter: &SeriesFilter) -> Self {
+        FieldDto {
+            name: field.name.as_str(),
+            historical: filter
+                .apply(&field.historical)
+                .iter()
+                .map(|value| HistoricalValueDto {
+                    x: value.x,
+                    y: value.y,
+                    date: game.date(value.x).to_iso_datetime(),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Query parameters to limit the returned historical values.
+///
+/// Numbers are received as text so invalid values are reported instead of being ignored.
+#[derive(FromForm, Debug)]
+pub struct SeriesQuery {
+    /// first game time to include
+    from: Option<String>,
+    /// last game time to include
+    to: Option<String>,
+    /// comma separated list of fields to return
+    fields: Option<String>,
+    /// maximum number of points per field, values are averaged when exceeded
+    max_points: Option<String>,
+}
+
+fn parse_param<T: std::str::FromStr>(
+    name: &str,
+    value: &Option<String>,
+) -> Result<Option<T>, ApiError> {
+    match value {
+        None => Ok(None),
+        Some(value) => value.trim().parse().map(Some).map_err(|_| {
+            ApiError::bad_request(
+                "invalid_parameter",
+                format!("invalid value {} for {}", value, name),
+            )
+        }),
+    }
+}
+
+impl SeriesQuery {
+    fn filter(&self) -> Result<SeriesFilter, ApiError> {
+        let from: Option<f64> = parse_param("from", &self.from)?;
+        let to: Option<f64> = parse_param("to", &self.to)?;
+        let max_points: Option<usize> = parse_param("max_points", &self.max_points)?;
+
+        if max_points == Some(0) {
+            return Err(ApiError::bad_request(
+                "invalid_max_points",
+                "max_points must be greater than zero".to_string(),
+            ));
+        }
+
+        for (name, value) in [("from", from), ("to", to)] {
+            if value.is_some_and(|value| !value.is_finite()) {
+                return Err(ApiError::bad_request(
+                    "invalid_range",
+                    format!("{} must be a finite game time", name),
+                ));
+            }
+        }
+
+        if let (Some(from), Some(to)) = (from, to) {
+            if from > to {
+                return Err(ApiError::bad_request(
+                    "invalid_range",
+                    format!("from {} is after to {}", from, to),
+                ));
+            }
+        }
+
+        Ok(SeriesFilter {
+            from,
+            to,
+            max_points,
+        })
+    }
+
+    fn accept_field(&self, name: &str) -> bool {
+        match &self.fields {
+            None => true,
+            Some(fields) => fields.split(',').any(|f| f.trim() == name),
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct PopulationDto<'a> {
+    population_id: u32,
+    population_name: &'a str,
+    fields: Vec<FieldDto<'a>>,
+}
+
+fn find_game(dashboard: &Dashboard, game_id: u32) -> Result<&GameDashboard, ApiError> {
+    dashboard
+        .games
+        .iter()
+        .find(|game| game.game_id == game_id)
+        .ok_or_else(|| ApiError::game_not_found(game_id))
+}
+
+fn find_population(
+    game: &GameDashboard,
+    population_id: u32,
+) -> Result<&DashboardPopulation, ApiError> {
+    game.populations
+        .iter()
+        .find(|pop| pop.population_id == population_id)
+        .ok_or_else(|| ApiError::population_not_found(population_id))
+}
+
+#[get("/games")]
+fn data_games(dashboard: &State<ServedDashboard>) -> Result<content::Json<String>, ApiError> {
+    let dashboard = get_data(dashboard)?;
+    let result: Vec<KeyValudDto> = dashboard
+        .games
+        .iter()
+        .map(|game| KeyValudDto {
+            id: game.game_id,
+            name: game.game_name.as_str(),
+        })
+        .collect();
+
+    let result_json = serde_json::to_string_pretty(&result)?;
+    Ok(content::Json(result_json))
+}
+
+#[get("/games/<game_id>/populations")]
+fn data_games_populations(
+    dashboard: &State<ServedDashboard>,
+    game_id: u32,
+) -> Result<content::Json<String>, ApiError> {
+    let dashboard = get_data(dashboard)?;
+    let game = find_game(&dashboard, game_id)?;
+    let result: Vec<KeyValudDto> = game
+        .populations
+        .iter()
+        .map(|pop| KeyValudDto {
+            id: pop.population_id,
+            name: pop.population_name.as_str(),
+        })
+        .collect();
+
+    let result_json = serde_json::to_string_pretty(&result)?;
+    Ok(content::Json(result_json))
+}
+
+#[get("/games/<game_id>/populations/<population_id>?<query..>")]
+fn data_games_populations_by_id(
+    dashboard: &State<ServedDashboard>,
+    game_id: u32,
+    population_id: u32,
+    query: SeriesQuery,
+) -> Result<content::Json<String>, ApiError> {
+    let dashboard = get_data(dashboard)?;
+    let filter = query.filter()?;
+    let game = find_game(&dashboard, game_id)?;
+    let pop = find_population(game, population_id)?;
+
+    let result = PopulationDto {
+        population_id: pop.population_id,
+        population_name: pop.population_name.as_str(),
+        fields: pop
+            .fields
+            .iter()
+            .filter(|field| query.accept_field(&field.name))
+            .map(|field| FieldDto::new(game, field, &filter))
+            .collect(),
+    };
+
+    let result_json = serde_json::to_string_pretty(&result)?;
+    Ok(content::Json(result_json))
+}
+
+#[derive(Serialize, Debug)]
+pub struct CustomDto<'a> {
+    game_id: u32,
+    fields: Vec<FieldDto<'a>>,
+}
+
+/// series of the user defined metrics
+#[get("/games/<game_id>/custom?<query..>")]
+fn data_games_custom(
+    dashboard: &State<ServedDashboard>,
+    game_id: u32,
+    query: SeriesQuery,
+) -> Result<content::Json<String>, ApiError> {
+    let dashboard = get_data(dashboard)?;
+    let filter = query.filter()?;
+    let game = find_game(&dashboard, game_id)?;
+
+    let result = CustomDto {
+        game_id,
+        fields: game
+            .custom
+            .iter()
+            .filter(|field| query.accept_field(&field.name))
+            .map(|field| FieldDto::new(game, field, &filter))
+            .collect(),
+    };
+
+    let result_json = serde_json::to_string_pretty(&result)?;
+    Ok(content::Json(result_json))
+}
+
+/// alerts raised by the alert rules ordered by game time, optionally of a single game
+#[get("/alerts?<game>")]
+fn data_alerts(
+    dashboard: &State<ServedDashboard>,
+    game: Option<String>,
+) -> Result<content::Json<String>, ApiError> {
+    let dashboard = get_data(dashboard)?;
+    let game_id: Option<u32> = parse_param("game", &game)?;
+    if let Some(game_id) = game_id {
+        find_game(&dashboard, game_id)?;
+    }
+
+    let mut result: Vec<&Alert> = dashboard
+        .games
+        .iter()
+        .filter(|game| game_id.is_none_or(|id| id == game.game_id))
+        .flat_map(|game| game.alerts.iter())
+        .collect();
+    result.sort_by(|a, b| a.game_time.total_cmp(&b.game_time));
+
+    let result_json = serde_json::to_string_pretty(&result)?;
+    Ok(content::Json(result_json))
+}
+
+/// unusual jumps of the game and population fields, optionally of a single population
+#[get("/games/<game_id>/anomalies?<population>&<threshold>")]
+fn data_games_anomalies(
+    dashboard: &State<ServedDashboard>,
+    config: &State<Config>,
+    game_id: u32,
+    population: Option<String>,
+    threshold: Option<String>,
+) -> Result<content::Json<String>, ApiError> {
+    let dashboard = get_data(dashboard)?;
+    let game = find_game(&dashboard, game_id)?;
+    let population_id: Option<u32> = parse_param("population", &population)?;
+    if let Some(population_id) = population_id {
+        find_population(game, population_id)?;
+    }
+
+    let mut cfg = config.anomaly_cfg();
+    if let Some(threshold) = parse_param("threshold", &threshold)? {
+        cfg.threshold = threshold;
+    }
+    cfg.validate()
+        .map_err(|e| ApiError::bad_request("invalid_threshold", e))?;
+
+    let mut result = cfg.detect(game);
+    if population_id.is_some() {
+        result.retain(|anomaly| anomaly.population_id == population_id);
+    }
+
+    let result_json = serde_json::to_string_pretty(&result)?;
+    Ok(content::Json(result_json))
+}
+
+#[derive(Serialize, Debug)]
+pub struct ProjectionDto<'a> {
+    population_id: u32,
+    population_name: &'a str,
+    /// growth rate per game year
+    growth_rate: f64,
+    /// estimated maximum population, `null` for exponential growth
+    capacity: Option<f64>,
+    near_capacity: bool,
+    projected: Vec<HistoricalValueDto>,
+}
+
+/// population growth models projected `years` ahead, optionally of a single population
+#[get("/games/<game_id>/projections?<population>&<years>")]
+fn data_games_projections(
+    dashboard: &State<ServedDashboard>,
+    game_id: u32,
+    population: Option<String>,
+    years: Option<String>,
+) -> Result<content::Json<String>, ApiError> {
+    let dashboard = get_data(dashboard)?;
+    let game = find_game(&dashboard, game_id)?;
+    let population_id: Option<u32> = parse_param("population", &population)?;
+    if let Some(population_id) = population_id {
+        find_population(game, population_id)?;
+    }
+    let years: f64 = parse_param("years", &years)?.unwrap_or(growth::DEFAULT_YEARS);
+    growth::validate_years(years).map_err(|e| ApiError::bad_request("invalid_years", e))?;
+
+    let projections = growth::project_game(game, years);
+    let result: Vec<ProjectionDto> = projections
+        .iter()
+        .filter(|p| population_id.is_none_or(|id| id == p.population_id))
+        .map(|p| ProjectionDto {
+            population_id: p.population_id,
+            population_name: p.population_name.as_str(),
+            growth_rate: p.model.rate,
+            capacity: p.model.capacity,
+            near_capacity: p.near_capacity,
+            projected: p
+                .projected
+                .iter()
+                .map(|value| HistoricalValueDto {
+                    x: value.x,
+                    y: value.y,
+                    date: game.date(value.x).to_iso_datetime(),
+                })
+                .collect(),
+        })
+        .collect();
+
+    let result_json = serde_json::to_string_pretty(&result)?;
+    Ok(content::Json(result_json))
+}
+
+/// `metric` of several games aligned by elapsed game years, `games` is a comma separated list
+/// of game ids, every game when absent
+#[get("/compare?<metric>&<games>")]
+fn data_compare(
+    dashboard: &State<ServedDashboard>,
+    metric: Option<String>,
+    games: Option<String>,
+) -> Result<content::Json<String>, ApiError> {
+    let dashboard = get_data(dashboard)?;
+    let metric = metric.ok_or_else(|| {
+        ApiError::bad_request("invalid_parameter", "metric is required".to_string())
+    })?;
+
+    let mut game_ids: Vec<u32> = vec![];
+    for id in games.iter().flat_map(|games| games.split(',')) {
+        let game_id = parse_param("games", &Some(id.to_string()))?.unwrap_or_default();
+        find_game(&dashboard, game_id)?;
+        game_ids.push(game_id);
+    }
+
+    let result = Comparison::new(&dashboard, &metric, &game_ids);
+    let result_json = serde_json::to_string_pretty(&result)?;
+    Ok(content::Json(result_json))
+}
+
+/// `<id>.csv` path segment
+#[derive(Debug)]
+pub struct CsvFile(u32);
+
+impl<'a> FromParam<'a> for CsvFile {
+    type Error = &'a str;
+
+    fn from_param(param: &'a str) -> Result<Self, Self::Error> {
+        param
+            .strip_suffix(".csv")
+            .and_then(|id| id.parse().ok())
+            .map(CsvFile)
+            .ok_or(param)
+    }
+}
+
+fn csv_response(
+    game: &GameDashboard,
+    target: ExportTarget,
+) -> Result<(ContentType, String), ApiError> {
+    let mut buffer = vec![];
+    export::write_csv(&mut buffer, game, target).map_err(|e| match e {
+        ExportError::NotFound(msg) => ApiError::not_found("population_not_found", msg),
+        ExportError::Io(e) => e.into(),
+    })?;
+
+    let body = String::from_utf8(buffer)
+        .map_err(|e| ApiError::internal("encoding_error", format!("{}", e)))?;
+    Ok((ContentType::CSV, body))
+}
+
+/// game wide fields as CSV
+#[get("/games/<game_file>")]
+fn data_games_csv(
+    dashboard: &State<ServedDashboard>,
+    game_file: CsvFile,
+) -> Result<(ContentType, String), ApiError> {
+    let dashboard = get_data(dashboard)?;
+    let game = find_game(&dashboard, game_file.0)?;
+    csv_response(game, ExportTarget::Game)
+}
+
+/// population fields summed over all populations as CSV
+#[get("/games/<game_id>/totals.csv")]
+fn data_games_totals_csv(
+    dashboard: &State<ServedDashboard>,
+    game_id: u32,
+) -> Result<(ContentType, String), ApiError> {
+    let dashboard = get_data(dashboard)?;
+    let game = find_game(&dashboard, game_id)?;
+    csv_response(game, ExportTarget::Totals)
+}
+
+/// user defined metrics as CSV
+#[get("/games/<game_id>/custom.csv")]
+fn data_games_custom_csv(
+    dashboard: &State<ServedDashboard>,
+    game_id: u32,
+) -> Result<(ContentType, String), ApiError> {
+    let dashboard = get_data(dashboard)?;
+    let game = find_game(&dashboard, game_id)?;
+    csv_response(game, ExportTarget::Custom)
+}
+
+#[get("/games/<game_id>/populations/<population_file>", rank = 2)]
+fn data_games_populations_csv(
+    dashboard: &State<ServedDashboard>,
+    game_id: u32,
+    population_file: CsvFile,
+) -> Result<(ContentType, String), ApiError> {
+    let dashboard = get_data(dashboard)?;
+    let game = find_game(&dashboard, game_id)?;
+    csv_response(game, ExportTarget::Population(population_file.0))
+}
+
+/// Stream a `snapshot` event every time the collector append a new snapshot
+#[get("/events")]
+fn events(queue: &State<Sender<SnapshotEvent>>, mut end: Shutdown) -> EventStream![] {
+    let mut rx = queue.subscribe();
+
+    EventStream! {
+        loop {
+            let msg = select! {
+                msg = rx.recv() => match msg {
+                    Ok(msg) => msg,
+                    Err(RecvError::Closed) => break,
+                    Err(RecvError::Lagged(_)) => continue,
+                },
+                _ = &mut end => break,
+            };
+
+            if let Ok(json) = serde_json::to_string(&msg) {
+                yield Event::data(json).event("snapshot");
+            }
+        }
+    }
+}
+
+/// Dashboard of the routes, either updated by the collector or reloaded from the dashboard file
+/// when another process changes it
+pub struct ServedDashboard {
+    dashboard: SharedDashboard,
+    /// dashboard file and its modification time when last loaded
+    file: Option<(String, Mutex<Option<SystemTime>>)>,
+}
+
+impl ServedDashboard {
+    /// dashboard kept up to date by the collector
+    pub fn shared(dashboard: SharedDashboard) -> Self {
+        ServedDashboard {
+            dashboard,
+            file: None,
+        }
+    }
+
+    /// dashboard read from `path`, and again every time the file changes, empty while the file
+    /// doesn't exist
+    pub fn file(path: &str) -> Result<Self, ServerError> {
+        // read before loading so a change while loading is noticed
+        let modified = std::fs::metadata(path).and_then(|m| m.modified()).ok();
+        let dashboard = load_dashboard(path, DashboardDb::load)?;
+
+        Ok(ServedDashboard {
+            dashboard: Arc::new(RwLock::new(dashboard)),
+            file: Some((path.to_string(), Mutex::new(modified))),
+        })
+    }
+
+    fn reload(&self) -> Result<(), ApiError> {
+        let (path, loaded) = match &self.file {
+            Some(file) => file,
+            None => return Ok(()),
+        };
+
+        let modified = match std::fs::metadata(path).and_then(|m| m.modified()) {
+            Ok(modified) => Some(modified),
+            Err(_) => return Ok(()),
+        };
+
+        let mut loaded = loaded.lock().map_err(|_| {
+            ApiError::internal("lock_poisoned", "dashboard lock poisoned".to_string())
+        })?;
+        if *loaded == modified {
+            return Ok(());
+        }
+
+        let dashboard = DashboardDb::load(path)
+            .map_err(|e| ApiError::internal("dashboard_error", format!("{:?}", e)))?;
+        *self.dashboard.write().map_err(|_| {
+            ApiError::internal("lock_poisoned", "dashboard lock poisoned".to_string())
+        })? = dashboard;
+        *loaded = modified;
+        Ok(())
+    }
+}
+
+pub fn rocket(
+    config: Config,
+    dashboard: ServedDashboard,
+    events_queue: Sender<SnapshotEvent>,
+) -> Rocket<Build> {
+    let figment = rocket::Config::figment().merge(Serialized::globals(&config));
+
+    rocket::custom(figment)
+        .manage(config)
+        .manage(dashboard)
+        .manage(events_queue)
+        .register("/", catchers![not_found, unprocessable, default_catcher])
+        .mount(
+            "/",
+            routes![
+                index,
+                assets,
+                data,
+                events,
+                data_games,
+                data_games_populations,
+                data_games_populations_by_id,
+                data_games_csv,
+                data_games_totals_csv,
+                data_games_custom,
+                data_games_custom_csv,
+                data_alerts,
+                data_games_anomalies,
+                data_games_projections,
+                data_compare,
+                data_games_populations_csv
+            ],
+        )
+}
+
+const EVENTS_QUEUE_CAPACITY: usize = 64;
+
+#[derive(Debug)]
+pub enum ServerError {
+    Generic(String),
+}
+
+/// Run the web server until it is shutdown.
+///
+/// When `watch` is set the Aurora database is monitored in a background thread and every new
+/// snapshot is appended into the dashboard being served, otherwise the dashboard file is reloaded
+/// whenever a separate collector updates it.
+pub fn serve(config: Config, watch: bool) -> Result<(), ServerError> {
+    let (events_queue, _) = broadcast::channel::<SnapshotEvent>(EVENTS_QUEUE_CAPACITY);
+
+    if !watch {
+        let dashboard = ServedDashboard::file(&config.dashboard_path)?;
+        return launch(rocket(config, dashboard, events_queue));
+    }
+
+    let mut dashboard = load_dashboard(&config.dump_path, DashboardDb::load_aurora_dump)?;
+    dashboard.derive(&config.derived);
+    dashboard.evaluate_alerts(&config.alerts);
+    let dashboard: SharedDashboard = Arc::new(RwLock::new(dashboard));
+
+    let cfg = CollectorCfg {
+        aurora_db: config
+            .aurora_db()
+            .map_err(|e| ServerError::Generic(format!("{:?}", e)))?,
+        dump_path: config.dump_path.clone(),
+        dashboard_path: config.dashboard_path.clone(),
+        keyframe_interval: config.keyframe_interval,
+        archive: config.archive_cfg(),
+        derived: config.derived.clone(),
+        alerts: config.alerts.clone(),
+        hooks: config.hooks(),
+    };
+
+    println!("monitoring {}", cfg.aurora_db.path().display());
+
+    let collector_dashboard = dashboard.clone();
+    let collector_queue = events_queue.clone();
+    std::thread::spawn(move || {
+        let listener = |event| {
+            // sending only fails when there is no browser listening
+            let _ = collector_queue.send(event);
+        };
+
+        if let Err(e) = collector::collect_into(cfg, collector_dashboard, listener) {
+            println!("collector stopped: {:?}", e);
+        }
+    });
+
+    launch(rocket(
+        config,
+        ServedDashboard::shared(dashboard),
+        events_queue,
+    ))
+}
+
+fn launch(rocket: Rocket<Build>) -> Result<(), ServerError> {
+    rocket::tokio::runtime::Builder::new_multi_thread()
+        // graceful shutdown depends on the "rocket-worker" prefix
+        .thread_name("rocket-worker-thread")
+        .enable_all()
+        .build()
+        .expect("create tokio runtime")
+        .block_on(rocket.launch())
+        .map_err(|e| ServerError::Generic(format!("{}", e)))
+}
+
+/// load the dashboard from the given file, a empty dashboard is used when the file still don't
+/// exist
+fn load_dashboard<F, E>(path: &str, loader: F) -> Result<Dashboard, ServerError>
+where
+    F: Fn(&str) -> Result<Dashboard, E>,
+    E: std::fmt::Debug,
+{
+    if !Path::new(path).exists() {
+        println!("{} not found, starting with a empty dashboard", path);
+        return Ok(Dashboard::new());
+    }
+
+    loader(path).map_err(|e| ServerError::Generic(format!("{:?}", e)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::alerts::AlertRule;
+    use crate::aurora_db::AuroraData;
+    use crate::dashboard::test::game_data;
+    use crate::field_map::Scope;
+    use rocket::http::ContentType;
+    use rocket::local::blocking::Client;
+
+    fn client() -> Client {
+        let mut dashboard = Dashboard::new();
+        let mut last = game_data(3.0);
+        last.custom.insert("ships".to_string(), 12.0);
+        dashboard.append(&AuroraData {
+            games: vec![game_data(1.0), game_data(2.0), last],
+        });
+        dashboard.evaluate_alerts(&[AlertRule {
+            field: "ships".to_string(),
+            scope: Scope::Game,
+            population: None,
+            below: None,
+            above: Some(10.0),
+        }]);
+
+        let (events_queue, _) = broadcast::channel(1);
+        let rocket = rocket(
+            Config::default(),
+            ServedDashboard::shared(Arc::new(RwLock::new(dashboard))),
+            events_queue,
+        );
+        Client::tracked(rocket).unwrap()
+    }
+
+    fn error_code(body: String) -> String {
+        let value: serde_json::Value = serde_json::from_str(&body).unwrap();
+        value["code"].as_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn server_should_serve_embedded_assets() {
+        let client = client();
+        let response = client.get("/").dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.content_type(), Some(ContentType::HTML));
+
+        let response = client.get("/assets/d3.v7.min.js").dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.content_type(), Some(ContentType::JavaScript));
+
+        let response = client.get("/assets/unknown.js").dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+    }
+
+    #[test]
+    fn server_should_list_populations() {
+        let client = client();
+        let response = client.get("/games/0/populations").dispatch();
+        assert_eq!(response.status(), Status::Ok);
+
+        let value: serde_json::Value =
+            serde_json::from_str(&response.into_string().unwrap()).unwrap();
+        assert_eq!(value[0]["id"], 1);
+        assert_eq!(value[0]["name"], "Pop 1");
+    }
+
+    #[test]
+    fn server_should_return_filtered_population() {
+        let client = client();
+        let response = client
+            .get("/games/0/populations/1?fields=population&from=2")
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+
+        let value: serde_json::Value =
+            serde_json::from_str(&response.into_string().unwrap()).unwrap();
+        assert_eq!(value["fields"].as_array().unwrap().len(), 1);
+        assert_eq!(
+            value["fields"][0]["historical"].as_array().unwrap().len(),
+            2
+        );
+    }
+
+    #[test]
+    fn server_should_export_csv() {
+        let client = client();
+        let response = client.get("/games/0/populations/1.csv").dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.content_type(), Some(ContentType::CSV));
+        let body = response.into_string().unwrap();
+        assert_eq!(body.lines().count(), 4);
+        assert!(body.starts_with("game_time,date,fuel_stockpile"));
+
+        let response = client.get("/games/0/totals.csv").dispatch();
+        assert_eq!(response.status(), Status::Ok);
+
+        let response = client.get("/games/0.csv").dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert!(response
+            .into_string()
+            .unwrap()
+            .starts_with("game_time,date,wealth"));
+
+        let response = client.get("/games/0/populations/9.csv").dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+
+        let response = client.get("/games/0/custom.csv").dispatch();
+        assert_eq!(
+            response.into_string().unwrap(),
+            "game_time,date,ships\n3,0002-01-01T00:00:03,12\n"
+        );
+    }
+
+    #[test]
+    fn server_should_return_custom_metrics() {
+        let client = client();
+        let response = client.get("/games/0/custom").dispatch();
+        assert_eq!(response.status(), Status::Ok);
+
+        let value: serde_json::Value =
+            serde_json::from_str(&response.into_string().unwrap()).unwrap();
+        assert_eq!(value["fields"][0]["name"], "ships");
+        assert_eq!(value["fields"][0]["historical"][0]["y"], 12.0);
+
+        let response = client.get("/games/9/custom").dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+    }
+
+    #[test]
+    fn server_should_return_alerts() {
+        let client = client();
+        let response = client.get("/alerts?game=0").dispatch();
+        assert_eq!(response.status(), Status::Ok);
+
+        let value: serde_json::Value =
+            serde_json::from_str(&response.into_string().unwrap()).unwrap();
+        assert_eq!(value.as_array().unwrap().len(), 1);
+        assert_eq!(value[0]["field"], "ships");
+        assert_eq!(value[0]["condition"], "above");
+        assert_eq!(value[0]["game_time"], 3.0);
+
+        let response = client.get("/alerts?game=9").dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+        let response = client.get("/alerts?game=x").dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+    }
+
+    #[test]
+    fn server_should_return_anomalies() {
+        let client = client();
+        let response = client.get("/games/0/anomalies?population=1").dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let value: serde_json::Value =
+            serde_json::from_str(&response.into_string().unwrap()).unwrap();
+        assert!(value.as_array().unwrap().is_empty());
+
+        let response = client.get("/games/0/anomalies?population=9").dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+        let response = client.get("/games/0/anomalies?threshold=x").dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+        let response = client.get("/games/0/anomalies?threshold=0").dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+    }
+
+    #[test]
+    fn server_should_return_projections() {
+        let client = client();
+        let response = client
+            .get("/games/0/projections?population=1&years=2")
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let value: serde_json::Value =
+            serde_json::from_str(&response.into_string().unwrap()).unwrap();
+        assert_eq!(value[0]["population_id"], 1);
+        assert_eq!(value[0]["growth_rate"], 0.0);
+        assert_eq!(value[0]["near_capacity"], false);
+        assert_eq!(value[0]["projected"].as_array().unwrap().len(), 9);
+        assert_eq!(value[0]["projected"][8]["y"], 3.0);
+
+        for years in &["x", "NaN", "inf", "-1", "1e9"] {
+            let response = client
+                .get(format!("/games/0/projections?years={}", years))
+                .dispatch();
+            assert_eq!(response.status(), Status::BadRequest);
+        }
+    }
+
+    #[test]
+    fn server_should_compare_games() {
+        let client = client();
+        let response = client.get("/compare?metric=population&games=0").dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let value: serde_json::Value =
+            serde_json::from_str(&response.into_string().unwrap()).unwrap();
+        assert_eq!(value["metric"], "population");
+        assert_eq!(value["games"][0]["game_id"], 0);
+        assert_eq!(value["games"][0]["values"].as_array().unwrap().len(), 3);
+
+        let response = client
+            .get("/compare?metric=population&games=0,9")
+            .dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+        let response = client.get("/compare?metric=population&games=x").dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+        let response = client.get("/compare").dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+    }
+
+    #[test]
+    fn server_should_reload_changed_dashboard_file() {
+        let path = std::env::temp_dir()
+            .join(format!(
+                "aurora_analytics_{}_served_dashboard.json",
+                std::process::id()
+            ))
+            .to_string_lossy()
+            .to_string();
+        let _ = std::fs::remove_file(&path);
+
+        let dashboard = ServedDashboard::file(&path).unwrap();
+        let (events_queue, _) = broadcast::channel(1);
+        let client = Client::tracked(rocket(Config::default(), dashboard, events_queue)).unwrap();
+        let games = |client: &Client| -> serde_json::Value {
+            let response = client.get("/games").dispatch();
+            serde_json::from_str(&response.into_string().unwrap()).unwrap()
+        };
+        assert_eq!(games(&client), serde_json::json!([]));
+
+        // a separate collector writes the dashboard
+        let mut dashboard = Dashboard::new();
+        dashboard.append(&AuroraData {
+            games: vec![game_data(1.0)],
+        });
+        DashboardDb::save(&dashboard, &path).unwrap();
+        assert_eq!(games(&client)[0]["name"], "Game 01");
+
+        dashboard.games[0].game_name = "Game 02".to_string();
+        DashboardDb::save(&dashboard, &path).unwrap();
+        // file systems with a coarse resolution may keep the same modification time
+        let later = SystemTime::now() + std::time::Duration::from_secs(5);
+        std::fs::File::options()
+            .write(true)
+            .open(&path)
+            .and_then(|file| file.set_modified(later))
+            .unwrap();
+        assert_eq!(games(&client)[0]["name"], "Game 02");
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn server_should_return_404_for_unknown_game() {
+        let client = client();
+        let response = client.get("/games/99/populations").dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+        assert_eq!(response.content_type(), Some(ContentType::JSON));
+        assert_eq!(
+            error_code(response.into_string().unwrap()),
+            "game_not_found"
+        );
+    }
+
+    #[test]
+    fn server_should_return_404_for_unknown_population() {
+        let client = client();
+        let response = client.get("/games/0/populations/99").dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+        assert_eq!(
+            error_code(response.into_string().unwrap()),
+            "population_not_found"
+        );
+    }
+
+    #[test]
+    fn server_should_return_404_for_unknown_route() {
+        let client = client();
+        let response = client.get("/unknown").dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+        assert_eq!(error_code(response.into_string().unwrap()), "not_found");
+    }
+
+    // the uri macro generated for the route is never used
+    #[allow(unused_imports)]
+    mod teapot {
+        use rocket::{get, http::Status};
+
+        #[get("/teapot")]
+        pub fn teapot() -> Status {
+            Status::ImATeapot
+        }
+    }
+
+    #[test]
+    fn server_should_keep_status_of_other_errors() {
+        let rocket = rocket::build()
+            .register("/", catchers![default_catcher])
+            .mount("/", routes![teapot::teapot]);
+        let client = Client::tracked(rocket).unwrap();
+
+        let response = client.get("/teapot").dispatch();
+        assert_eq!(response.status(), Status::ImATeapot);
+        assert_eq!(response.content_type(), Some(ContentType::JSON));
+        assert_eq!(error_code(response.into_string().unwrap()), "im_a_teapot");
+
+        assert_eq!(status_code(Status::MethodNotAllowed), "method_not_allowed");
+        assert_eq!(status_code(Status::new(599)), "status_599");
+    }
+
+    #[test]
+    fn server_should_return_400_for_invalid_parameters() {
+        let client = client();
+        let response = client.get("/games/0/populations/1?max_points=0").dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+        assert_eq!(
+            error_code(response.into_string().unwrap()),
+            "invalid_max_points"
+        );
+
+        for query in ["from=5&to=1", "from=NaN", "to=inf", "from=-inf&to=1"] {
+            let response = client
+                .get(format!("/games/0/populations/1?{}", query))
+                .dispatch();
+            assert_eq!(response.status(), Status::BadRequest, "{}", query);
+            assert_eq!(error_code(response.into_string().unwrap()), "invalid_range");
+        }
+
+        let response = client
+            .get("/games/0/populations/1?max_points=abc")
+            .dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+        assert_eq!(
+            error_code(response.into_string().unwrap()),
+            "invalid_parameter"
+        );
+    }
+}